@@ -1,19 +1,269 @@
 #![allow(clippy::missing_panics_doc)]
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use clap::{Arg, Command};
-use serde::Deserialize;
+use grhooks_origin::Origin;
+use serde::{Deserialize, Deserializer};
+
+pub use crate::secret::Secret;
+
+mod quoting;
+mod secret;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct WebhookConfig {
     pub path: String,
-    pub secret: Option<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub origin: Vec<Origin>,
+    /// Accepted secrets, any of them validates a delivery to allow rotating keys
+    #[serde(default, alias = "secrets", deserialize_with = "one_or_many")]
+    pub secret: Vec<Secret>,
+    /// Files holding a secret, such as Docker secrets in `/run/secrets`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub secret_file: Vec<PathBuf>,
+    /// Names of systemd credentials looked up in `$CREDENTIALS_DIRECTORY`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub secret_credential: Vec<String>,
+    /// Why a `secret_file` or `secret_credential` could not be loaded, deliveries are then rejected
+    #[serde(skip)]
+    pub secret_error: Option<String>,
+    /// Seconds a signed timestamp may differ from the local clock, for Standard Webhooks and GitLab
+    /// signing tokens
+    pub tolerance: Option<u64>,
     pub events: HashSet<String>,
+    /// Expression over the payload variables, deliveries where it is false are skipped
+    #[serde(rename = "if", alias = "when")]
+    pub condition: Option<String>,
+    /// Globs of changed files, push deliveries touching none of them are skipped
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Globs of changed files, push deliveries touching only these are skipped
+    #[serde(default)]
+    pub paths_ignore: Vec<String>,
     pub shell: Option<Vec<String>>,
+    /// How payload values are placed in the command and script
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Exports the payload variables to the environment of the command
+    pub export: Option<ExportConfig>,
+    /// How the raw payload is handed to the command
+    #[serde(default)]
+    pub payload: PayloadMode,
     pub command: Option<String>,
     pub script: Option<PathBuf>,
+    /// Directory the command runs in, the one of grhooks by default
+    pub workdir: Option<PathBuf>,
+    /// Extra environment variables of the command, their values are templates
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Start the command without the environment of grhooks
+    #[serde(default)]
+    pub clear_env: bool,
+    /// User name or uid the command runs as, grhooks must run as root
+    pub user: Option<String>,
+    /// Group name or gid the command runs as, the primary group of `user` by default
+    pub group: Option<String>,
+    /// Resource limits of the command, on Linux
+    pub limits: Option<LimitsConfig>,
+    /// Restrictions of the command, on Linux
+    pub sandbox: Option<SandboxConfig>,
+    pub replay: Option<ReplayConfig>,
+    #[serde(default)]
+    pub mode: ExecutionMode,
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    /// Seconds the command may run before its process group is terminated
+    pub timeout: Option<u64>,
+    /// Seconds between `SIGTERM` and `SIGKILL` once the timeout expired
+    #[serde(default = "default_timeout_grace")]
+    pub timeout_grace: u64,
+    pub retry: Option<RetryConfig>,
+}
+
+const fn default_timeout_grace() -> u64 {
+    5
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Splice values quoted for the shell, `raw(...)` opts out
+    #[default]
+    Quote,
+    /// Splice references to environment variables holding the values, never the values
+    Env,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadMode {
+    #[default]
+    None,
+    /// Pipe the payload to the standard input of the command
+    Stdin,
+    /// Write the payload to a temporary file, whose path is in `GRHOOKS_PAYLOAD_FILE`
+    File,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct LimitsConfig {
+    /// Seconds of CPU time before the command gets `SIGXCPU`, then `SIGKILL`
+    pub cpu: Option<u64>,
+    /// MiB of virtual memory
+    pub memory: Option<u64>,
+    /// Open file descriptors
+    pub open_files: Option<u64>,
+    /// Processes of the user running the command, including the ones outside of it
+    pub processes: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SandboxConfig {
+    /// Forbid gaining privileges through setuid binaries or file capabilities
+    #[serde(default)]
+    pub no_new_privs: bool,
+    /// Paths the command may read and execute, any other path is denied through Landlock
+    #[serde(default)]
+    pub read_only: Vec<PathBuf>,
+    /// Paths the command may read, execute and modify
+    #[serde(default)]
+    pub read_write: Vec<PathBuf>,
+    #[serde(default)]
+    pub namespaces: Vec<Namespace>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Namespace {
+    /// Run as the same user inside a new user namespace, which allows the other ones without root
+    User,
+    /// Private mounts
+    Mount,
+    /// No network besides an unconfigured loopback
+    Network,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExportConfig {
+    /// Globs over variable names, such as `event.repository.*`, every variable when empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Globs over variable names left out of the environment
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Upper bound in bytes for the exported variables, the rest are left out
+    #[serde(default = "default_export_max_size")]
+    pub max_size: usize,
+}
+
+#[must_use]
+pub const fn default_export_max_size() -> usize {
+    128 * 1024
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionMode {
+    /// Answer the delivery once the command finished, with its output
+    #[default]
+    Sync,
+    /// Answer `202 Accepted` with a job ID and run the command in the background
+    Async,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConcurrencyPolicy {
+    /// Run every delivery as soon as it arrives
+    #[default]
+    Parallel,
+    /// Run one delivery at a time, in arrival order
+    Queue,
+    /// Cancel the running command when a newer delivery arrives
+    CancelInProgress,
+    /// Drop deliveries arriving while a command is running
+    SkipIfRunning,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ConcurrencyConfig {
+    #[serde(default)]
+    pub policy: ConcurrencyPolicy,
+    /// Template grouping deliveries, e.g. `${{event.ref}}` to handle every branch on its own
+    pub key: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry, doubled on every attempt
+    #[serde(default = "default_retry_backoff")]
+    pub backoff: u64,
+    /// Upper bound in seconds for the wait between attempts
+    #[serde(default = "default_retry_max_backoff")]
+    pub max_backoff: u64,
+    /// Exit codes worth retrying, any failure is retried when empty
+    #[serde(default)]
+    pub exit_codes: Vec<i32>,
+}
+
+const fn default_retry_attempts() -> u32 {
+    3
+}
+
+const fn default_retry_backoff() -> u64 {
+    1
+}
+
+const fn default_retry_max_backoff() -> u64 {
+    60
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayAction {
+    /// Answer duplicated deliveries with `409 Conflict`
+    #[default]
+    Reject,
+    /// Answer duplicated deliveries with `200 OK` without running the command
+    Ignore,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReplayConfig {
+    #[serde(default)]
+    pub action: ReplayAction,
+    /// Number of delivery IDs remembered for the webhook
+    #[serde(default = "default_replay_capacity")]
+    pub capacity: usize,
+    /// File where seen delivery IDs are stored to survive restarts
+    pub persist: Option<PathBuf>,
+}
+
+const fn default_replay_capacity() -> usize {
+    1024
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -22,9 +272,43 @@ pub struct Config {
     pub port: u16,
     #[serde(skip)]
     pub verbose: String,
+    /// Default `timeout` for every webhook, including the ones of other files in the directory
+    pub timeout: Option<u64>,
+    pub history: Option<HistoryConfig>,
+    /// Bearer token required by the admin endpoints, which are disabled without it
+    pub admin_token: Option<Secret>,
+    /// Empty in files of a directory that only hold global settings
+    #[serde(default)]
     pub webhooks: Vec<WebhookConfig>,
 }
 
+/// What the binary was asked to do from the command line
+#[derive(Clone, Debug)]
+pub enum CliCommand {
+    /// Run the webhook server
+    Serve,
+    /// Send a stored delivery through the webhook pipeline again
+    Replay {
+        delivery_id: String,
+        skip_signature: bool,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HistoryConfig {
+    /// JSON Lines file where every delivery is appended
+    pub path: PathBuf,
+    /// Number of deliveries kept in the file
+    #[serde(default = "default_history_entries")]
+    pub max_entries: usize,
+    /// Days after which deliveries are removed from the file
+    pub max_age_days: Option<u64>,
+}
+
+const fn default_history_entries() -> usize {
+    1000
+}
+
 const fn default_port() -> u16 {
     8080
 }
@@ -34,6 +318,9 @@ impl Default for Config {
         Self {
             port: 8080,
             verbose: "info".to_string(),
+            timeout: None,
+            history: None,
+            admin_token: None,
             webhooks: Vec::new(),
         }
     }
@@ -41,6 +328,9 @@ impl Default for Config {
 
 impl Config {
     pub fn merge(&mut self, other: Config) {
+        self.history = self.history.take().or(other.history);
+        self.timeout = self.timeout.or(other.timeout);
+        self.admin_token = self.admin_token.take().or(other.admin_token);
         // merge the two webhooks list without repeating the same path
         // in case of conflict, merge the events
         for other_webhook in other.webhooks {
@@ -51,6 +341,11 @@ impl Config {
             {
                 let mut existing_webhook = self.webhooks.remove(index);
                 existing_webhook.events.extend(other_webhook.events);
+                for origin in other_webhook.origin {
+                    if !existing_webhook.origin.contains(&origin) {
+                        existing_webhook.origin.push(origin);
+                    }
+                }
                 self.webhooks.push(existing_webhook);
             } else {
                 self.webhooks.push(other_webhook);
@@ -70,39 +365,133 @@ impl Config {
                     .collect::<Vec<_>>()
                     .join(", ")
             );
+            if !webhook.origin.is_empty() {
+                println!(
+                    "\tOrigins: {}",
+                    webhook
+                        .origin
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
         }
     }
 }
 
-#[must_use]
-pub fn parse_config(path: &PathBuf) -> Config {
+impl WebhookConfig {
+    /// Appends the secrets stored in `secret_file` and `secret_credential` to `secret`.
+    ///
+    /// A source that cannot be read sets `secret_error` instead of leaving the webhook unsigned.
+    pub fn load_secret_files(&mut self) {
+        self.secret_error = None;
+        let credentials_dir = std::env::var_os("CREDENTIALS_DIRECTORY").map(PathBuf::from);
+        let mut files = self.secret_file.clone();
+        for name in &self.secret_credential {
+            match credentials_dir.as_ref() {
+                Some(dir) => files.push(dir.join(name)),
+                None => {
+                    self.secret_error = Some(format!(
+                        "cannot load credential {name:?}: $CREDENTIALS_DIRECTORY is not set"
+                    ));
+                }
+            }
+        }
+
+        for file in files {
+            match std::fs::read_to_string(&file) {
+                Ok(secret) if !secret.trim().is_empty() => self
+                    .secret
+                    .push(Secret::new(secret.trim_end_matches(['\r', '\n']))),
+                Ok(_) => {
+                    self.secret_error = Some(format!("secret file {} is empty", file.display()));
+                }
+                Err(e) => {
+                    self.secret_error =
+                        Some(format!("cannot read secret file {}: {e}", file.display()));
+                }
+            }
+        }
+
+        if let Some(error) = &self.secret_error {
+            eprintln!("Rejecting every delivery to webhook {}: {error}", self.path);
+        }
+    }
+
+    /// Templates of the command or script written inside quotes, comments or heredocs, which payload
+    /// values can escape
+    #[must_use]
+    pub fn quoted_templates(&self) -> Vec<String> {
+        let script = self
+            .script
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        [self.command.as_deref(), script.as_deref()]
+            .into_iter()
+            .flatten()
+            .flat_map(quoting::quoted_templates)
+            .map(|template| format!("${{{{ {template} }}}}"))
+            .collect()
+    }
+}
+
+/// Reads the configuration, failing when a webhook splices values where their quoting can be escaped
+///
+/// # Errors
+/// Lists every webhook with templates inside quotes, comments or heredocs
+pub fn parse_config(path: &PathBuf) -> Result<Config, String> {
+    let mut config = read_config(path);
+    let mut errors = Vec::new();
+    for webhook in &mut config.webhooks {
+        // The default applies to the webhooks of every file in the directory
+        webhook.timeout = webhook.timeout.or(config.timeout);
+        // Secret files are read on every load, so hot reloads also pick up rotated secrets
+        webhook.load_secret_files();
+
+        let templates = webhook.quoted_templates();
+        if !templates.is_empty() {
+            errors.push(format!(
+                "webhook {}: values are quoted for the shell, move {} out of quotes, comments and heredocs",
+                webhook.path,
+                templates.join(", ")
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn read_config(path: &PathBuf) -> Config {
     if path.is_dir() {
         let mut config = Config::default();
         for entry in std::fs::read_dir(path).unwrap() {
             let entry = entry.unwrap();
             if entry.path().is_file() {
                 let cfg_content = std::fs::read_to_string(entry.path()).unwrap();
-                config.merge(
-                    toml::from_str(&cfg_content)
-                        .or_else(|_| serde_yaml::from_str(&cfg_content))
-                        .or_else(|_| serde_json::from_str(&cfg_content))
-                        .unwrap_or_default(),
-                );
+                config.merge(parse_content(&cfg_content));
             }
         }
         config
     } else {
         let cfg_content = std::fs::read_to_string(path).unwrap();
-
-        toml::from_str(&cfg_content)
-            .or_else(|_| serde_yaml::from_str(&cfg_content))
-            .or_else(|_| serde_json::from_str(&cfg_content))
-            .unwrap_or_default()
+        parse_content(&cfg_content)
     }
 }
 
+fn parse_content(cfg_content: &str) -> Config {
+    toml::from_str(cfg_content)
+        .or_else(|_| serde_yaml::from_str(cfg_content))
+        .or_else(|_| serde_json::from_str(cfg_content))
+        .unwrap_or_default()
+}
+
 #[must_use]
-pub fn get_config() -> (PathBuf, Config) {
+pub fn get_config() -> (PathBuf, Config, CliCommand) {
     let args = Command::new("grhooks")
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
@@ -124,6 +513,21 @@ pub fn get_config() -> (PathBuf, Config) {
                 .action(clap::ArgAction::Count)
                 .help("Enable verbose logging"),
         )
+        .subcommand(
+            Command::new("replay")
+                .about("Send a delivery stored in the history through its webhook again")
+                .arg(
+                    Arg::new("delivery-id")
+                        .required(true)
+                        .help("ID of the stored delivery"),
+                )
+                .arg(
+                    Arg::new("skip-signature")
+                        .long("skip-signature")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Don't validate the signature of the stored delivery"),
+                ),
+        )
         .color(clap::ColorChoice::Always)
         .get_matches();
 
@@ -135,7 +539,94 @@ pub fn get_config() -> (PathBuf, Config) {
         std::env::var("GRHOOKS_LOG").unwrap_or_else(|_| args.get_count("verbose").to_string());
 
     println!("Reading configs from path: {config_path:?}");
-    let config = parse_config(config_path);
+    let config = parse_config(config_path).unwrap_or_else(|errors| {
+        eprintln!("Invalid configuration:\n{errors}");
+        std::process::exit(1);
+    });
+
+    let command = match args.subcommand() {
+        Some(("replay", args)) => CliCommand::Replay {
+            delivery_id: args
+                .get_one::<String>("delivery-id")
+                .expect("No delivery ID provided")
+                .clone(),
+            skip_signature: args.get_flag("skip-signature"),
+        },
+        _ => CliCommand::Serve,
+    };
+
+    (config_path.clone(), Config { verbose, ..config }, command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_config;
+
+    #[test]
+    fn directory_timeout_applies_to_every_file() {
+        let dir = std::env::temp_dir().join(format!("grhooks-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("global.toml"), "timeout = 30\n").unwrap();
+        std::fs::write(
+            dir.join("hooks.toml"),
+            "[[webhooks]]\npath = \"a\"\nevents = [\"*\"]\ncommand = \"true\"\n\n\
+             [[webhooks]]\npath = \"b\"\nevents = [\"*\"]\ncommand = \"true\"\ntimeout = 5\n",
+        )
+        .unwrap();
+
+        let config = parse_config(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let timeout = |path: &str| {
+            config
+                .webhooks
+                .iter()
+                .find(|webhook| webhook.path == path)
+                .and_then(|webhook| webhook.timeout)
+        };
+        assert_eq!(config.timeout, Some(30));
+        assert_eq!(timeout("a"), Some(30));
+        assert_eq!(timeout("b"), Some(5));
+    }
 
-    (config_path.clone(), Config { verbose, ..config })
+    #[test]
+    fn unreadable_secret_files_reject_the_webhook() {
+        let dir = std::env::temp_dir().join(format!("grhooks-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("secret"), "s3cr3t\n").unwrap();
+        let config_file = dir.join("hooks.toml");
+        std::fs::write(
+            &config_file,
+            format!(
+                "[[webhooks]]\npath = \"ok\"\nevents = [\"*\"]\ncommand = \"true\"\n\
+                 secret_file = {:?}\n\n\
+                 [[webhooks]]\npath = \"missing\"\nevents = [\"*\"]\ncommand = \"true\"\n\
+                 secret_file = {:?}\n",
+                dir.join("secret"),
+                dir.join("missing"),
+            ),
+        )
+        .unwrap();
+
+        let config = parse_config(&config_file).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let webhook = |path: &str| {
+            config
+                .webhooks
+                .iter()
+                .find(|webhook| webhook.path == path)
+                .unwrap()
+        };
+        assert_eq!(webhook("ok").secret.len(), 1);
+        assert_eq!(webhook("ok").secret[0].expose(), "s3cr3t");
+        assert!(webhook("ok").secret_error.is_none());
+        assert_eq!(webhook("missing").secret.len(), 0);
+        assert!(
+            webhook("missing")
+                .secret_error
+                .as_ref()
+                .is_some_and(|error| error.contains("cannot read secret file"))
+        );
+    }
 }
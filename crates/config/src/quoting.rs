@@ -0,0 +1,224 @@
+//! Finds templates in shell quotes, comments or heredocs, where their own quoting breaks
+
+/// Template functions whose result is spliced as is, and can be quoted by the command
+const UNQUOTED_FUNCTIONS: [&str; 2] = ["raw(", "env("];
+
+/// Templates of `text` where the quotes added to their values don't protect the command: inside single
+/// or double-quoted strings, comments and heredoc bodies.
+///
+/// Values are spliced already quoted, so `"${{x}}"` renders as `"'$(id)'"` where `$(id)` still runs. In
+/// comments and heredocs quotes are plain characters, and a value with a newline ends them early.
+pub(crate) fn quoted_templates(text: &str) -> Vec<&str> {
+    let mut templates = Vec::new();
+    let mut quote = None;
+    // Delimiters of the heredocs whose body starts on the next line, and whether tabs are stripped
+    let mut heredocs = Vec::new();
+    let mut word_start = true;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(template) = rest.strip_prefix("${{") {
+            let Some(end) = template.find("}}") else {
+                break;
+            };
+            let expression = template[..end].trim();
+            if quote.is_some() && !is_unquoted(expression) {
+                templates.push(expression);
+            }
+            rest = &template[end + 2..];
+            word_start = false;
+            continue;
+        }
+
+        match (quote, c) {
+            (None | Some('"'), '\\') => {
+                // The escaped character is skipped along with the backslash
+                let mut chars = rest.chars();
+                chars.next();
+                chars.next();
+                rest = chars.as_str();
+                word_start = false;
+                continue;
+            }
+            (None, '#') if word_start => {
+                let end = rest.find('\n').unwrap_or(rest.len());
+                templates.extend(templates_in(&rest[..end]));
+                // The newline is kept, it may start heredoc bodies
+                rest = &rest[end..];
+                continue;
+            }
+            (None, '<') if rest.starts_with("<<") && !rest.starts_with("<<<") => {
+                let (heredoc, next) = heredoc_delimiter(&rest[2..]);
+                heredocs.push(heredoc);
+                rest = next;
+                word_start = false;
+                continue;
+            }
+            (None, '\n') if !heredocs.is_empty() => {
+                rest = &rest[1..];
+                for (delimiter, strip_tabs) in heredocs.drain(..) {
+                    rest = heredoc_body(rest, &delimiter, strip_tabs, &mut templates);
+                }
+                word_start = true;
+                continue;
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+        word_start = quote.is_none() && (c.is_whitespace() || ";&|()".contains(c));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    templates
+}
+
+fn is_unquoted(expression: &str) -> bool {
+    UNQUOTED_FUNCTIONS
+        .iter()
+        .any(|function| expression.starts_with(function))
+}
+
+/// Every template of `text`, regardless of quotes
+fn templates_in(text: &str) -> impl Iterator<Item = &str> {
+    text.split("${{")
+        .skip(1)
+        .filter_map(|template| template.split_once("}}"))
+        .map(|(expression, _)| expression.trim())
+        .filter(|expression| !is_unquoted(expression))
+}
+
+/// Reads the delimiter after `<<`, unquoted as the shell matches it against the body lines
+fn heredoc_delimiter(text: &str) -> ((String, bool), &str) {
+    let (strip_tabs, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let text = text.trim_start_matches([' ', '\t']);
+    let end = text
+        .find(|c: char| c.is_whitespace() || ";&|()<>".contains(c))
+        .unwrap_or(text.len());
+    let delimiter = text[..end].replace(['\'', '"', '\\'], "");
+    ((delimiter, strip_tabs), &text[end..])
+}
+
+/// Skips the body of a heredoc up to its delimiter line, collecting its templates
+fn heredoc_body<'a>(
+    mut text: &'a str,
+    delimiter: &str,
+    strip_tabs: bool,
+    templates: &mut Vec<&'a str>,
+) -> &'a str {
+    while !text.is_empty() {
+        let (line, next) = text.split_once('\n').unwrap_or((text, ""));
+        text = next;
+        let line_delimiter = if strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            line
+        };
+        if line_delimiter == delimiter {
+            break;
+        }
+        templates.extend(templates_in(line));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quoted_templates;
+
+    #[test]
+    fn plain_templates_are_accepted() {
+        assert_eq!(
+            quoted_templates("echo ${{event.ref}} ${{ event.number }}"),
+            [] as [&str; 0]
+        );
+        assert_eq!(quoted_templates("app=${{event.image}}"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn double_quoted_templates_are_found() {
+        assert_eq!(
+            quoted_templates(r#"echo "title=${{event.title}}" ${{event.ref}}"#),
+            ["event.title"]
+        );
+        assert_eq!(
+            quoted_templates(r#"echo "a \" ${{ event.a }}" "b""#),
+            ["event.a"]
+        );
+    }
+
+    #[test]
+    fn single_quoted_templates_are_found() {
+        assert_eq!(quoted_templates("echo '${{event.title}}'"), ["event.title"]);
+        assert_eq!(
+            quoted_templates(r#"echo '"' ${{event.title}}"#),
+            [] as [&str; 0]
+        );
+    }
+
+    #[test]
+    fn escaped_quotes_do_not_open_strings() {
+        assert_eq!(
+            quoted_templates(r#"echo \" ${{event.title}} \""#),
+            [] as [&str; 0]
+        );
+    }
+
+    #[test]
+    fn unquoted_functions_are_accepted() {
+        assert_eq!(
+            quoted_templates(r#"echo "${{ raw(event.ref) }}""#),
+            [] as [&str; 0]
+        );
+        assert_eq!(
+            quoted_templates(r#"docker pull "${{ env("IMAGE") }}:latest""#),
+            [] as [&str; 0]
+        );
+    }
+
+    #[test]
+    fn comments_do_not_open_quotes() {
+        assert_eq!(
+            quoted_templates("# don't deploy on weekends\n./deploy.sh ${{event.ref}}"),
+            [] as [&str; 0]
+        );
+        assert_eq!(
+            quoted_templates("./deploy.sh ${{event.ref}} # it's fine\necho ${{event.after}}"),
+            [] as [&str; 0]
+        );
+        assert_eq!(
+            quoted_templates("echo a#'${{event.title}}'"),
+            ["event.title"]
+        );
+    }
+
+    #[test]
+    fn templates_in_comments_are_found() {
+        assert_eq!(
+            quoted_templates("# deploying ${{event.title}}\n./deploy.sh"),
+            ["event.title"]
+        );
+    }
+
+    #[test]
+    fn heredoc_bodies_are_found() {
+        assert_eq!(
+            quoted_templates(
+                "cat <<EOF\ntitle: ${{event.title}}\nit's done\nEOF\necho ${{event.ref}}"
+            ),
+            ["event.title"]
+        );
+        assert_eq!(
+            quoted_templates("cat <<-'END' > notes\n\t${{event.a}}\n\tEND\necho ${{event.b}}"),
+            ["event.a"]
+        );
+        assert_eq!(
+            quoted_templates("cat <<A <<B\n${{event.a}}\nA\n${{event.b}}\nB\n"),
+            ["event.a", "event.b"]
+        );
+        assert_eq!(quoted_templates("cat <<< ${{event.a}}"), [] as [&str; 0]);
+    }
+}
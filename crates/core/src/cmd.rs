@@ -1,16 +1,68 @@
-use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-use grhooks_config::WebhookConfig;
+use grhooks_config::{PayloadMode, WebhookConfig};
 use srtemplate::SrTemplate;
+use tempfile::TempPath;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::process::{Deadline, LineSink, OutputLine, ProcessOutput};
+use crate::sandbox::Sandbox;
+use crate::shell::CommandTemplate;
+use crate::user::Credentials;
+use crate::{Error, Redactor};
+
+/// Per delivery options of a command execution
+#[derive(Clone, Debug, Default)]
+pub struct ExecuteOptions {
+    /// Receives the redacted stdout/stderr lines while the command runs
+    pub output: Option<UnboundedSender<OutputLine>>,
+    /// Exported as `GRHOOKS_DELIVERY_ID`
+    pub delivery_id: Option<String>,
+    /// Raw body of the delivery, passed as configured by the `payload` of the webhook
+    pub payload: Option<Arc<[u8]>>,
+}
 
 pub async fn execute_command(
     config: &WebhookConfig,
     event_type: &str,
     value: &serde_json::Value,
-) -> std::io::Result<String> {
-    let ctx = SrTemplate::with_delimiter("${{", "}}");
-    ctx.add_variable("event.type", event_type);
-    crate::process_value(&ctx, "event", value);
+    options: &ExecuteOptions,
+) -> Result<String, Error> {
+    let redactor = Redactor::new(config, event_type);
+    let sink = options.output.clone().map(|sender| LineSink {
+        sender,
+        redactor: redactor.clone(),
+    });
+    // Read once, so every attempt runs the script the exported variables were selected for
+    let script = config
+        .script
+        .as_ref()
+        .map(std::fs::read_to_string)
+        .transpose()?;
+    let Some(source) = script.as_deref().or(config.command.as_deref()) else {
+        return Err(Error::MissingCommand);
+    };
+    let mut template = CommandTemplate::new(config, &redactor, event_type, value, source)?;
+    let credentials = Credentials::resolve(config)?;
+
+    // The file is kept until every attempt finished, and removed once dropped
+    let Payload {
+        stdin,
+        file: payload_file,
+    } = pass_payload(config, options, credentials.as_ref(), &mut template)?;
+    let CommandTemplate { ctx, env, .. } = template;
+    let sandbox = Sandbox::new(config, credentials.as_ref(), payload_file.as_deref())?;
+
+    // Environment values are not read by a shell, so they get the payload values as they are
+    let env_ctx = SrTemplate::with_delimiter("${{", "}}");
+    if !config.env.is_empty() {
+        env_ctx.add_variable("event.type", event_type);
+        crate::process_value(&env_ctx, &redactor, "event", value);
+        if let Some(path) = &payload_file {
+            env_ctx.add_variable("payload_file", path.display().to_string());
+        }
+    }
 
     let (shell, args) = if let Some(shell) = config.shell.as_ref() {
         let mut args = shell.clone();
@@ -20,61 +72,157 @@ pub async fn execute_command(
         ("sh".to_string(), vec!["-c".to_string()])
     };
 
-    let output = if let Some(script_path) = &config.script {
-        execute_script(&ctx, script_path, &shell, &args).await?
-    } else if let Some(command) = config.command.as_deref() {
-        execute_direct_command(&ctx, command, &shell, &args).await?
-    } else {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "No command or script provided",
-        ));
+    let deadline = Deadline {
+        timeout: config.timeout.map(Duration::from_secs),
+        grace: Duration::from_secs(config.timeout_grace),
     };
 
-    Ok(output)
+    let mut attempt = 1;
+    loop {
+        ctx.add_variable("attempt", attempt);
+        env_ctx.add_variable("attempt", attempt);
+        let mut command = tokio::process::Command::new(&shell);
+        if config.clear_env {
+            command.env_clear();
+        }
+        command
+            .args(&args)
+            .envs(env.iter().cloned())
+            .env("GRHOOKS_ATTEMPT", attempt.to_string());
+        if let Some(delivery_id) = &options.delivery_id {
+            command.env("GRHOOKS_DELIVERY_ID", delivery_id);
+        }
+        if let Some(credentials) = &credentials {
+            credentials.apply(&mut command);
+        }
+        if let Some(workdir) = &config.workdir {
+            command.current_dir(workdir);
+        }
+        for (name, value) in &config.env {
+            let Ok(value) = env_ctx.render(value) else {
+                return Err(Error::Render("env"));
+            };
+            command.env(name, value);
+        }
+
+        let run = Run {
+            redactor: &redactor,
+            deadline,
+            sink: sink.as_ref(),
+            stdin: stdin.as_ref(),
+            sandbox: sandbox.as_ref(),
+        };
+        let result = if script.is_some() {
+            execute_script(&ctx, run, command, source).await
+        } else {
+            execute_direct_command(&ctx, run, command, source).await
+        };
+
+        match (result, &config.retry) {
+            (Err(e), Some(retry))
+                if attempt < retry.max_attempts && crate::retry::is_retryable(retry, &e) =>
+            {
+                let delay = crate::retry::backoff(retry, attempt);
+                tracing::warn!(
+                    "Attempt {attempt}/{} for {:?} failed, retrying in {delay:?}: {e}",
+                    retry.max_attempts,
+                    config.path
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+/// Raw payload handed to the command
+#[derive(Default)]
+struct Payload {
+    stdin: Option<Arc<[u8]>>,
+    file: Option<TempPath>,
+}
+
+/// Hands the raw payload to the command as configured, through stdin or a temporary file
+fn pass_payload(
+    config: &WebhookConfig,
+    options: &ExecuteOptions,
+    credentials: Option<&Credentials>,
+    template: &mut CommandTemplate,
+) -> Result<Payload, Error> {
+    match (config.payload, &options.payload) {
+        (PayloadMode::Stdin, Some(payload)) => Ok(Payload {
+            stdin: Some(payload.clone()),
+            file: None,
+        }),
+        (PayloadMode::File, Some(payload)) => {
+            let file = tempfile::NamedTempFile::new()?;
+            std::fs::write(&file, payload)?;
+            let path = file.into_temp_path();
+            #[cfg(unix)]
+            if let Some(credentials) = credentials {
+                std::os::unix::fs::chown(&path, credentials.uid, credentials.gid)?;
+            }
+            template.add_variable(
+                "payload_file".to_string(),
+                "GRHOOKS_PAYLOAD_FILE".to_string(),
+                path.display().to_string(),
+                true,
+            );
+            Ok(Payload {
+                stdin: None,
+                file: Some(path),
+            })
+        }
+        _ => Ok(Payload::default()),
+    }
+}
+
+/// How a single attempt runs and reports its output
+#[derive(Clone, Copy)]
+struct Run<'a> {
+    redactor: &'a Redactor,
+    deadline: Deadline,
+    sink: Option<&'a LineSink>,
+    stdin: Option<&'a Arc<[u8]>>,
+    sandbox: Option<&'a Sandbox<'a>>,
 }
 
 async fn execute_direct_command(
     ctx: &SrTemplate<'_>,
-    command: &str,
-    shell: &str,
-    shell_args: &[String],
-) -> std::io::Result<String> {
-    let Ok(rendered_cmd) = ctx.render(command.trim()) else {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to render command",
-        ));
+    run: Run<'_>,
+    mut command: tokio::process::Command,
+    command_text: &str,
+) -> Result<String, Error> {
+    let Ok(rendered_cmd) = ctx.render(command_text.trim()) else {
+        return Err(Error::Render("command"));
     };
-    tracing::debug!("Executing command: {}", rendered_cmd);
+    tracing::debug!("Executing command: {}", run.redactor.redact(&rendered_cmd));
 
-    let output = tokio::process::Command::new(shell)
-        .args(shell_args)
-        .arg(&rendered_cmd)
-        .output()
-        .await?;
+    command.arg(&rendered_cmd);
+    if let Some(sandbox) = run.sandbox {
+        sandbox.apply(&mut command, None)?;
+    }
+    let output = crate::process::run(command, run.deadline, run.sink, run.stdin.cloned()).await?;
 
-    handle_command_output(&output, &rendered_cmd)
+    handle_command_output(&output, run.redactor, run.deadline, &rendered_cmd)
 }
 
 async fn execute_script(
     ctx: &SrTemplate<'_>,
-    script_path: &PathBuf,
-    shell: &str,
-    shell_args: &[String],
-) -> std::io::Result<String> {
-    let script_content = std::fs::read_to_string(script_path)?;
-
+    run: Run<'_>,
+    mut command: tokio::process::Command,
+    script_content: &str,
+) -> Result<String, Error> {
     let Ok(rendered_script) = ctx.render(script_content.trim()) else {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Failed to render script",
-        ));
+        return Err(Error::Render("script"));
     };
 
     let temp_script = tempfile::NamedTempFile::new()?;
 
     std::fs::write(&temp_script, rendered_script)?;
+    // Executing a file that is still open for writing fails with `ETXTBSY`
+    let temp_script = temp_script.into_temp_path();
 
     #[cfg(unix)]
     {
@@ -86,29 +234,57 @@ async fn execute_script(
 
     tracing::debug!("Executing rendered script: {temp_script:?}");
 
-    let output = tokio::process::Command::new(shell)
-        .args(shell_args)
-        .arg(temp_script.path())
-        .output()
-        .await?;
+    command.arg(&temp_script);
+    if let Some(sandbox) = run.sandbox {
+        sandbox.apply(&mut command, Some(&temp_script))?;
+    }
+    let output = crate::process::run(command, run.deadline, run.sink, run.stdin.cloned()).await?;
 
-    handle_command_output(&output, &format!("script: {temp_script:?}"))
+    handle_command_output(
+        &output,
+        run.redactor,
+        run.deadline,
+        &format!("script: {}", temp_script.display()),
+    )
 }
 
-fn handle_command_output(output: &std::process::Output, context: &str) -> std::io::Result<String> {
-    if !output.status.success() {
+fn handle_command_output(
+    output: &ProcessOutput,
+    redactor: &Redactor,
+    deadline: Deadline,
+    context: &str,
+) -> Result<String, Error> {
+    let Some(status) = output.status else {
+        let err_msg = format!(
+            "Command timed out after {}s ({}):\nSTDERR: {}\nSTDOUT: {}",
+            deadline.timeout.unwrap_or_default().as_secs(),
+            context,
+            String::from_utf8_lossy(&output.stderr),
+            String::from_utf8_lossy(&output.stdout)
+        );
+
+        return Err(Error::TimedOut {
+            message: redactor.redact(&err_msg).into_owned(),
+        });
+    };
+
+    if !status.success() {
         let err_msg = format!(
             "Command failed ({} - {}):\nSTDERR: {}\nSTDOUT: {}",
-            output.status,
+            status,
             context,
             String::from_utf8_lossy(&output.stderr),
             String::from_utf8_lossy(&output.stdout)
         );
 
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, err_msg));
+        return Err(Error::Failed {
+            exit_code: status.code(),
+            message: redactor.redact(&err_msg).into_owned(),
+        });
     }
 
-    let output_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let output_str = String::from_utf8_lossy(&output.stdout);
+    let output_str = redactor.redact(output_str.trim()).into_owned();
     tracing::debug!("Command Output: {}", output_str);
     Ok(output_str)
 }
@@ -0,0 +1,532 @@
+//! Boolean expressions deciding whether a delivery runs its command, e.g.
+//! `event.ref == "refs/heads/main" && !(event.sender.login in ["dependabot[bot]"])`
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::Error;
+
+/// Evaluates a condition against the same variables available to templates
+pub fn evaluate_condition(condition: &str, event_type: &str, value: &Value) -> Result<bool, Error> {
+    let tokens = tokenize(condition)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        return Err(invalid(format!("unexpected {token:?}")));
+    }
+
+    let vars = Variables { event_type, value };
+    Ok(truthy(&expr.evaluate(&vars)?))
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::Condition(message.into())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+    Not,
+    And,
+    Or,
+    Compare(CompareOp),
+    In,
+    Exists,
+    Literal(Value),
+    Variable(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Matches,
+    NotMatches,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let mut chars = input.chars().peekable();
+    let mut tokens = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = match c {
+            '(' => single(&mut chars, Token::Open),
+            ')' => single(&mut chars, Token::Close),
+            '[' => single(&mut chars, Token::OpenList),
+            ']' => single(&mut chars, Token::CloseList),
+            ',' => single(&mut chars, Token::Comma),
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(invalid(format!("expected '{c}{c}'")));
+                }
+                if c == '&' { Token::And } else { Token::Or }
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                match (c, chars.next_if(|&next| next == '=' || next == '~')) {
+                    ('=', Some('=')) => Token::Compare(CompareOp::Eq),
+                    ('=', Some('~')) => Token::Compare(CompareOp::Matches),
+                    ('!', Some('=')) => Token::Compare(CompareOp::Ne),
+                    ('!', Some('~')) => Token::Compare(CompareOp::NotMatches),
+                    ('!', None) => Token::Not,
+                    ('<', Some('=')) => Token::Compare(CompareOp::Le),
+                    ('<', None) => Token::Compare(CompareOp::Lt),
+                    ('>', Some('=')) => Token::Compare(CompareOp::Ge),
+                    ('>', None) => Token::Compare(CompareOp::Gt),
+                    _ => return Err(invalid(format!("unknown operator after '{c}'"))),
+                }
+            }
+            '"' | '\'' => Token::Literal(Value::String(string(&mut chars)?)),
+            '-' | '0'..='9' => Token::Literal(number(&mut chars)?),
+            c if c.is_alphabetic() || c == '_' => {
+                let word = variable(&mut chars);
+                match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    "in" => Token::In,
+                    "exists" => Token::Exists,
+                    _ => Token::Variable(word),
+                }
+            }
+            c => return Err(invalid(format!("unexpected character '{c}'"))),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn single(chars: &mut Peekable<Chars>, token: Token) -> Token {
+    chars.next();
+    token
+}
+
+fn string(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+    let quote = chars.next();
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some(c) => string.push(c),
+                None => break,
+            },
+            Some(c) if Some(c) == quote => return Ok(string),
+            Some(c) => string.push(c),
+            None => break,
+        }
+    }
+    Err(invalid("unterminated string"))
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Result<Value, Error> {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '.')) {
+        number.push(c);
+    }
+    serde_json::from_str(&number).map_err(|_| invalid(format!("invalid number {number:?}")))
+}
+
+fn variable(chars: &mut Peekable<Chars>) -> String {
+    let mut name = String::new();
+    loop {
+        while let Some(c) = chars.next_if(|&c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            name.push(c);
+        }
+        if name == "in" {
+            break;
+        }
+
+        // Array indices are part of the name, unlike lists: `event.commits[0].id`
+        let mut lookahead = chars.clone();
+        if lookahead.next() != Some('[') {
+            break;
+        }
+        let index =
+            std::iter::from_fn(|| lookahead.next_if(char::is_ascii_digit)).collect::<String>();
+        if index.is_empty() || lookahead.next() != Some(']') {
+            break;
+        }
+        name.push('[');
+        name.push_str(&index);
+        name.push(']');
+        *chars = lookahead;
+    }
+    name
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Variable(String),
+    List(Vec<Expr>),
+    Exists(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    In(Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.position) == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(invalid(format!("expected {token:?}")))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Error> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+
+        let left = self.parse_primary()?;
+        match self.tokens.get(self.position) {
+            Some(Token::Compare(op)) => {
+                let op = *op;
+                self.position += 1;
+                let right = self.parse_primary()?;
+                Ok(Expr::Compare(op, Box::new(left), Box::new(right)))
+            }
+            Some(Token::In) => {
+                self.position += 1;
+                let right = self.parse_primary()?;
+                Ok(Expr::In(Box::new(left), Box::new(right)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, Error> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.parse_or()?;
+                self.expect(&Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::OpenList) => {
+                let mut items = Vec::new();
+                if !self.eat(&Token::CloseList) {
+                    loop {
+                        items.push(self.parse_primary()?);
+                        if self.eat(&Token::CloseList) {
+                            break;
+                        }
+                        self.expect(&Token::Comma)?;
+                    }
+                }
+                Ok(Expr::List(items))
+            }
+            Some(Token::Exists) => {
+                self.expect(&Token::Open)?;
+                let Some(Token::Variable(name)) = self.next() else {
+                    return Err(invalid("exists() expects a variable"));
+                };
+                self.expect(&Token::Close)?;
+                Ok(Expr::Exists(name))
+            }
+            Some(Token::Literal(value)) => Ok(Expr::Literal(value)),
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(token) => Err(invalid(format!("unexpected {token:?}"))),
+            None => Err(invalid("unexpected end of condition")),
+        }
+    }
+}
+
+struct Variables<'a> {
+    event_type: &'a str,
+    value: &'a Value,
+}
+
+impl Variables<'_> {
+    /// Resolves names like `event.commits[0].author.name`, as flattened by `process_value`
+    fn get(&self, name: &str) -> Option<Value> {
+        let path = name.strip_prefix("event")?;
+        match lookup(self.value, path) {
+            Some(value) => Some(value.clone()),
+            None if path == ".type" => Some(Value::String(self.event_type.to_string())),
+            None => None,
+        }
+    }
+}
+
+fn lookup<'a>(mut value: &'a Value, mut path: &str) -> Option<&'a Value> {
+    while !path.is_empty() {
+        if let Some(rest) = path.strip_prefix('.') {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            value = value.get(&rest[..end])?;
+            path = &rest[end..];
+        } else {
+            let rest = path.strip_prefix('[')?;
+            let end = rest.find(']')?;
+            value = value.get(rest[..end].parse::<usize>().ok()?)?;
+            path = &rest[end + 1..];
+        }
+    }
+    Some(value)
+}
+
+impl Expr {
+    fn evaluate(&self, vars: &Variables) -> Result<Value, Error> {
+        Ok(match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Variable(name) => vars.get(name).unwrap_or(Value::Null),
+            Expr::List(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| item.evaluate(vars))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Exists(name) => Value::Bool(vars.get(name).is_some()),
+            Expr::Not(expr) => Value::Bool(!truthy(&expr.evaluate(vars)?)),
+            Expr::And(left, right) => {
+                Value::Bool(truthy(&left.evaluate(vars)?) && truthy(&right.evaluate(vars)?))
+            }
+            Expr::Or(left, right) => {
+                Value::Bool(truthy(&left.evaluate(vars)?) || truthy(&right.evaluate(vars)?))
+            }
+            Expr::Compare(op, left, right) => {
+                Value::Bool(compare(*op, &left.evaluate(vars)?, &right.evaluate(vars)?)?)
+            }
+            Expr::In(needle, haystack) => {
+                let needle = needle.evaluate(vars)?;
+                Value::Bool(match haystack.evaluate(vars)? {
+                    Value::Array(items) => items.iter().any(|item| equals(&needle, item)),
+                    Value::String(text) => needle.as_str().is_some_and(|n| text.contains(n)),
+                    Value::Object(map) => needle.as_str().is_some_and(|n| map.contains_key(n)),
+                    _ => false,
+                })
+            }
+        })
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => (left - right).abs() < f64::EPSILON,
+        _ => left == right,
+    }
+}
+
+fn compare(op: CompareOp, left: &Value, right: &Value) -> Result<bool, Error> {
+    let ordering = match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64().partial_cmp(&r.as_f64()),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        _ => None,
+    };
+
+    Ok(match op {
+        CompareOp::Eq => equals(left, right),
+        CompareOp::Ne => !equals(left, right),
+        CompareOp::Lt => ordering.is_some_and(std::cmp::Ordering::is_lt),
+        CompareOp::Le => ordering.is_some_and(std::cmp::Ordering::is_le),
+        CompareOp::Gt => ordering.is_some_and(std::cmp::Ordering::is_gt),
+        CompareOp::Ge => ordering.is_some_and(std::cmp::Ordering::is_ge),
+        CompareOp::Matches | CompareOp::NotMatches => {
+            let Value::String(pattern) = right else {
+                return Err(invalid("the right side of =~ must be a string"));
+            };
+            let regex = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+            let matches = left.as_str().is_some_and(|text| regex.is_match(text));
+            matches == (op == CompareOp::Matches)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::evaluate_condition;
+    use crate::Error;
+
+    fn payload() -> Value {
+        json!({
+            "ref": "refs/heads/main",
+            "draft": false,
+            "number": 42,
+            "labels": ["bug", "deploy"],
+            "sender": {"login": "dependabot[bot]", "site_admin": null},
+            "commits": [{"id": "abc", "message": "fix: typo"}, {"id": "def"}],
+        })
+    }
+
+    fn eval(condition: &str) -> bool {
+        evaluate_condition(condition, "push", &payload())
+            .unwrap_or_else(|e| panic!("{condition}: {e}"))
+    }
+
+    fn fails(condition: &str) -> bool {
+        matches!(
+            evaluate_condition(condition, "push", &payload()),
+            Err(Error::Condition(_))
+        )
+    }
+
+    #[test]
+    fn comparisons() {
+        assert!(eval(r#"event.ref == "refs/heads/main""#));
+        assert!(eval("event.ref != 'refs/heads/dev'"));
+        assert!(eval(
+            "event.number == 42.0 && event.number >= 42 && event.number < 100"
+        ));
+        assert!(eval(r#"event.ref > "refs/heads/a""#));
+        assert!(!eval("event.ref > 1"));
+        assert!(eval(
+            r#"event.ref =~ "^refs/heads/" && event.ref !~ "tags""#
+        ));
+        assert!(eval("event.type == 'push'"));
+    }
+
+    #[test]
+    fn precedence() {
+        // `!` negates the whole comparison
+        assert!(eval("!event.number == 41"));
+        assert!(!eval("!event.number == 42"));
+        assert!(eval("!event.draft"));
+        // `&&` binds tighter than `||`
+        assert!(eval("event.draft && event.draft || event.number == 42"));
+        assert!(!eval("event.draft && (event.draft || event.number == 42)"));
+        assert!(eval(
+            "event.number == 1 || event.number == 2 || !(event.draft)"
+        ));
+    }
+
+    #[test]
+    fn in_lists_strings_and_objects() {
+        assert!(eval(
+            r#"event.sender.login in ["dependabot[bot]", "renovate[bot]"]"#
+        ));
+        assert!(!eval("event.number in [1, 2]"));
+        assert!(eval("42 in [1, 42.0]"));
+        assert!(eval(r#""deploy" in event.labels"#));
+        assert!(eval(r#""heads" in event.ref"#));
+        assert!(eval(r#""login" in event.sender"#));
+        assert!(!eval(r#""name" in event.sender"#));
+        assert!(!eval("event.missing in event.labels"));
+        assert!(!eval("event.ref in []"));
+    }
+
+    #[test]
+    fn exists() {
+        assert!(eval("exists(event.draft)"));
+        assert!(eval("exists(event.sender.site_admin)"));
+        assert!(!eval("exists(event.sender.name)"));
+        assert!(!eval("event.sender.site_admin"));
+        assert!(eval("!exists(event.commits[2])"));
+    }
+
+    #[test]
+    fn array_indices() {
+        assert!(eval(r#"event.commits[0].id == "abc""#));
+        assert!(eval(
+            r#"event.commits[1].id == "def" && event.labels[1] == "deploy""#
+        ));
+        assert!(eval("event.commits[0].message =~ '^fix'"));
+        assert!(!eval("event.commits[5].id"));
+    }
+
+    #[test]
+    fn truthiness() {
+        assert!(eval(
+            "event.ref && event.number && event.labels && event.sender"
+        ));
+        assert!(!eval(
+            "event.missing || event.draft || 0 || '' || null || []"
+        ));
+    }
+
+    #[test]
+    fn malformed_conditions() {
+        for condition in [
+            "",
+            "event.ref ==",
+            "(event.ref",
+            "event.ref)",
+            "event.ref & event.draft",
+            "event.ref = 'x'",
+            "'unterminated",
+            "event.ref 'x'",
+            "[1, 2",
+            "exists('x')",
+            "exists(event.ref",
+            "1.2.3 == 1",
+            "event.ref =~ 1",
+            "event.ref =~ '('",
+            "event.ref @ 1",
+        ] {
+            assert!(fails(condition), "{condition:?} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Payload variables exported to the environment of commands
+
+use std::collections::HashSet;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use grhooks_config::{Interpolation, WebhookConfig, default_export_max_size};
+
+use crate::Error;
+
+/// Longest `NAME=value` string Linux accepts in an environment, larger ones fail the spawn with `E2BIG`
+const MAX_ARG_STRLEN: usize = 32 * 4096;
+
+/// Environment variable holding a template variable, e.g. `GRHOOKS_EVENT_PULL_REQUEST_TITLE`
+pub(crate) fn env_name(variable: &str) -> String {
+    let mut name = String::from("GRHOOKS_");
+    for c in variable.chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() => name.push(c.to_ascii_uppercase()),
+            ']' => {}
+            _ if name.ends_with('_') => {}
+            _ => name.push('_'),
+        }
+    }
+    name
+}
+
+fn globs(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        set.add(Glob::new(pattern).map_err(|e| Error::Pattern(e.to_string()))?);
+    }
+    set.build().map_err(|e| Error::Pattern(e.to_string()))
+}
+
+/// Variables referenced by the templates of `source`, such as `event.pull_request.title`
+pub(crate) fn references(source: &str) -> HashSet<&str> {
+    let mut references = HashSet::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("${{") {
+        rest = &rest[start + 3..];
+        let end = rest.find("}}").unwrap_or(rest.len());
+        let mut expression = &rest[..end];
+        rest = &rest[end..];
+
+        while !expression.is_empty() {
+            // String literals are arguments of functions, not variables
+            if let Some(literal) = expression.strip_prefix('"') {
+                expression = literal.find('"').map_or("", |end| &literal[end + 1..]);
+                continue;
+            }
+            let length = expression
+                .find(|c: char| !(c.is_alphanumeric() || "_.[]".contains(c)))
+                .unwrap_or(expression.len());
+            let (word, remainder) = expression.split_at(length.max(1));
+            if !remainder.trim_start().starts_with('(')
+                && word.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            {
+                references.insert(word);
+            }
+            expression = remainder;
+        }
+    }
+    references
+}
+
+/// Selects the variables exported for a delivery, within the size cap of the webhook
+pub(crate) struct Exporter<'a> {
+    /// Referenced by the command in `env` interpolation, which reads them from the environment
+    referenced: HashSet<&'a str>,
+    /// Include and exclude globs of `export`
+    globs: Option<(GlobSet, GlobSet)>,
+    max_size: usize,
+}
+
+impl<'a> Exporter<'a> {
+    /// `None` when the webhook exports no variables
+    pub fn new(config: &WebhookConfig, source: &'a str) -> Result<Option<Self>, Error> {
+        let referenced = match config.interpolation {
+            Interpolation::Env => references(source),
+            Interpolation::Quote => HashSet::new(),
+        };
+        let globs = match &config.export {
+            Some(export) => Some((globs(&export.include)?, globs(&export.exclude)?)),
+            None if referenced.is_empty() => return Ok(None),
+            None => None,
+        };
+
+        Ok(Some(Self {
+            referenced,
+            globs,
+            max_size: config
+                .export
+                .as_ref()
+                .map_or_else(default_export_max_size, |export| export.max_size),
+        }))
+    }
+
+    /// Which of the `(variable, env name, value)` are exported, the referenced ones taking the size
+    /// cap first
+    pub fn select(&self, variables: &[(String, String, String)]) -> Vec<bool> {
+        let mut exported = vec![false; variables.len()];
+        let mut remaining = self.max_size;
+
+        let referenced = variables
+            .iter()
+            .enumerate()
+            .filter(|(_, (variable, ..))| self.referenced.contains(variable.as_str()));
+        let globbed = variables.iter().enumerate().filter(|(_, (variable, ..))| {
+            self.globs.as_ref().is_some_and(|(include, exclude)| {
+                (include.is_empty() || include.is_match(variable)) && !exclude.is_match(variable)
+            })
+        });
+
+        for (index, (_, name, value)) in referenced.chain(globbed) {
+            if exported[index] {
+                continue;
+            }
+            // As laid out in the environment block: `NAME=value\0`
+            let size = name.len() + value.len() + 2;
+            if size > MAX_ARG_STRLEN {
+                tracing::warn!(
+                    "Not exporting {name}, its {} bytes exceed the system limit",
+                    value.len()
+                );
+            } else if size > remaining {
+                tracing::warn!("Not exporting {name}, the export size limit was reached");
+            } else {
+                remaining -= size;
+                exported[index] = true;
+            }
+        }
+        exported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Exporter, MAX_ARG_STRLEN, env_name, references};
+
+    fn variable(name: &str, value: String) -> (String, String, String) {
+        (name.to_string(), env_name(name), value)
+    }
+
+    #[test]
+    fn env_names() {
+        assert_eq!(
+            env_name("event.commits[0].id"),
+            "GRHOOKS_EVENT_COMMITS_0_ID"
+        );
+        assert_eq!(
+            env_name("event.pull-request.x"),
+            "GRHOOKS_EVENT_PULL_REQUEST_X"
+        );
+    }
+
+    #[test]
+    fn references_skip_functions_and_literals() {
+        let source =
+            r#"./run ${{ event.ref }} ${{raw(event.commits[0].id)}} ${{ env("event.secret") }}"#;
+        assert_eq!(
+            references(source),
+            HashSet::from(["event.ref", "event.commits[0].id"])
+        );
+    }
+
+    #[test]
+    fn referenced_variables_are_exported_first() {
+        let exporter = Exporter {
+            referenced: HashSet::from(["event.title"]),
+            globs: Some((globset::GlobSet::empty(), globset::GlobSet::empty())),
+            max_size: 100,
+        };
+        let variables = [
+            variable("event.body", "x".repeat(80)),
+            variable("event.title", "title".to_string()),
+            variable("event.id", "1".to_string()),
+        ];
+        assert_eq!(exporter.select(&variables), [false, true, true]);
+    }
+
+    #[test]
+    fn oversized_values_are_skipped() {
+        let exporter = Exporter {
+            referenced: HashSet::from(["event.title", "event.id"]),
+            globs: None,
+            max_size: usize::MAX,
+        };
+        let variables = [
+            variable("event.title", "x".repeat(MAX_ARG_STRLEN)),
+            variable("event.id", "1".to_string()),
+            variable("event.other", "2".to_string()),
+        ];
+        assert_eq!(exporter.select(&variables), [false, true, false]);
+    }
+}
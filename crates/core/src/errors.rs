@@ -0,0 +1,60 @@
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    MissingCommand,
+    Cancelled,
+    Render(&'static str),
+    /// The `if` expression of the webhook could not be parsed or evaluated
+    Condition(String),
+    /// A `paths` or `paths_ignore` glob of the webhook is invalid
+    Pattern(String),
+    /// The `user` or `group` of the webhook cannot be used
+    User(String),
+    /// The `limits` or `sandbox` of the webhook cannot be applied
+    Sandbox(String),
+    Failed {
+        exit_code: Option<i32>,
+        message: String,
+    },
+    /// The command was terminated after its timeout, the message holds its partial output
+    TimedOut {
+        message: String,
+    },
+}
+
+impl Error {
+    /// Exit code of the command, if it ran and exited normally
+    #[must_use]
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Error::Failed { exit_code, .. } => *exit_code,
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{error}"),
+            Error::MissingCommand => write!(f, "No command or script provided"),
+            Error::Cancelled => write!(f, "Command cancelled by a newer delivery"),
+            Error::Render(what) => write!(f, "Failed to render {what}"),
+            Error::Condition(message) => write!(f, "Invalid condition: {message}"),
+            Error::Pattern(message) => write!(f, "Invalid path pattern: {message}"),
+            Error::User(message) => write!(f, "Invalid user or group: {message}"),
+            Error::Sandbox(message) => write!(f, "Cannot sandbox command: {message}"),
+            Error::Failed { message, .. } | Error::TimedOut { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
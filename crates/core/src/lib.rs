@@ -1,61 +1,101 @@
 #![allow(clippy::missing_errors_doc)]
 
+use grhooks_config::{Secret, WebhookConfig};
 use serde_json::Value;
 use srtemplate::SrTemplate;
 
 mod cmd;
+mod condition;
+mod env;
+mod errors;
+mod paths;
+mod process;
+mod redact;
+mod retry;
+mod sandbox;
+mod shell;
+mod user;
 
-pub use cmd::execute_command;
+pub use cmd::{ExecuteOptions, execute_command};
+pub use condition::evaluate_condition;
+pub use errors::Error;
+pub use paths::matches_paths;
+pub use process::{OutputLine, OutputStream};
+pub use redact::Redactor;
 
-pub fn render_secret(secret: &str, event_type: &str) -> String {
+pub fn render_secret(secret: &Secret, event_type: &str) -> Secret {
     let ctx = SrTemplate::with_delimiter("${{", "}}");
     ctx.add_variable("event.type", event_type);
-    let secret = ctx.render(secret).unwrap_or_else(|_| secret.to_string());
-    tracing::debug!("Rendering secret: {secret}");
+    let secret = ctx
+        .render(secret.expose())
+        .map_or_else(|_| secret.clone(), Secret::new);
+    tracing::trace!("Rendered secret for event {event_type}");
     secret
 }
 
-pub fn process_value<'a>(ctx: &SrTemplate<'a>, prefix: &'a str, value: &'a Value) {
+/// Renders a template with the same variables available to commands
+pub fn render_template(template: &str, event_type: &str, value: &Value) -> Result<String, Error> {
+    let ctx = SrTemplate::with_delimiter("${{", "}}");
+    ctx.add_variable("event.type", event_type);
+    ctx.add_variable("attempt", 1);
+    process_value(&ctx, &Redactor::default(), "event", value);
+    ctx.render(template).map_err(|_| Error::Render("template"))
+}
+
+/// Describes what a delivery runs: the rendered command without secrets, or the script path
+#[must_use]
+pub fn describe_command(config: &WebhookConfig, event_type: &str, value: &Value) -> Option<String> {
+    if let Some(script) = &config.script {
+        return Some(format!("script: {}", script.display()));
+    }
+
+    let command = config.command.as_deref()?.trim();
+    let redactor = Redactor::new(config, event_type);
+    let rendered = shell::CommandTemplate::new(config, &redactor, event_type, value, command)
+        .ok()
+        .and_then(|template| {
+            template.ctx.add_variable("attempt", 1);
+            template.ctx.render(command).ok()
+        })
+        .unwrap_or_else(|| command.to_string());
+    Some(redactor.redact(&rendered).into_owned())
+}
+
+/// Flattens a payload into template variables, such as `event.commits[0].id`
+#[must_use]
+pub fn flatten(prefix: &str, value: &Value) -> Vec<(String, String)> {
+    let mut variables = Vec::new();
+    flatten_into(&mut variables, prefix.to_string(), value);
+    variables
+}
+
+fn flatten_into(variables: &mut Vec<(String, String)>, prefix: String, value: &Value) {
     match value {
-        Value::Null => {
-            tracing::trace!("Processing value: {prefix} = {value}");
-            ctx.add_variable(prefix, "null");
-        }
-        Value::Bool(b) => {
-            tracing::trace!("Processing value: {prefix} = {value}");
-            ctx.add_variable(prefix, b);
-        }
-        Value::Number(n) => {
-            tracing::trace!("Processing value: {prefix} = {value}");
-            ctx.add_variable(prefix, n);
-        }
-        Value::String(s) => {
-            tracing::trace!("Processing value: {prefix} = {value}");
-            ctx.add_variable(prefix, s);
-        }
+        Value::Null => variables.push((prefix, "null".to_string())),
+        Value::Bool(b) => variables.push((prefix, b.to_string())),
+        Value::Number(n) => variables.push((prefix, n.to_string())),
+        Value::String(s) => variables.push((prefix, s.clone())),
         Value::Array(arr) => {
-            tracing::trace!("Processing value: {prefix} = Array {{}}");
             for (i, item) in arr.iter().enumerate() {
-                let key = format!("{prefix}[{i}]");
-                let key = unsafe {
-                    core::mem::transmute::<&str, &'a str>(Box::leak(key.into_boxed_str()))
-                };
-                process_value(ctx, key, item);
+                flatten_into(variables, format!("{prefix}[{i}]"), item);
             }
         }
         Value::Object(obj) => {
-            tracing::trace!("Processing value: {prefix} = Object {{}}");
             for (k, v) in obj {
                 let key = if prefix.is_empty() {
-                    k.to_string()
+                    k.clone()
                 } else {
                     format!("{prefix}.{k}")
                 };
-                let key = unsafe {
-                    core::mem::transmute::<&str, &'a str>(Box::leak(key.into_boxed_str()))
-                };
-                process_value(ctx, key, v);
+                flatten_into(variables, key, v);
             }
         }
     }
 }
+
+pub fn process_value(ctx: &SrTemplate<'_>, redactor: &Redactor, prefix: &str, value: &Value) {
+    for (name, value) in flatten(prefix, value) {
+        tracing::trace!("Processing value: {name} = {}", redactor.redact(&value));
+        ctx.add_variable(name, value);
+    }
+}
@@ -0,0 +1,179 @@
+//! Changed-file filters for push deliveries, like `on.push.paths` in GitHub Actions
+
+use std::collections::BTreeSet;
+
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use grhooks_config::WebhookConfig;
+use serde_json::Value;
+
+use crate::Error;
+
+/// Files added, modified or removed by the commits of a push payload, from GitHub or GitLab
+fn changed_files(value: &Value) -> Option<BTreeSet<&str>> {
+    let commits = value["commits"].as_array()?;
+    Some(
+        commits
+            .iter()
+            .flat_map(|commit| {
+                ["added", "modified", "removed"]
+                    .into_iter()
+                    .filter_map(|list| commit[list].as_array())
+                    .flatten()
+            })
+            .filter_map(Value::as_str)
+            .collect(),
+    )
+}
+
+fn compile(pattern: &str) -> Result<GlobMatcher, Error> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .as_ref()
+        .map(Glob::compile_matcher)
+        .map_err(|e| Error::Pattern(e.to_string()))
+}
+
+/// Patterns applied in order, a later `!pattern` excludes what earlier ones included
+struct Patterns(Vec<(bool, GlobMatcher)>);
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self, Error> {
+        patterns
+            .iter()
+            .map(|pattern| match pattern.strip_prefix('!') {
+                Some(pattern) => Ok((false, compile(pattern)?)),
+                None => Ok((true, compile(pattern)?)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn matches(&self, file: &str) -> bool {
+        self.0
+            .iter()
+            .rev()
+            .find(|(_, matcher)| matcher.is_match(file))
+            .is_some_and(|(include, _)| *include)
+    }
+}
+
+/// Whether a delivery touches the `paths` of the webhook and not only its `paths_ignore`.
+///
+/// Payloads without `commits` are not filtered, as their changed files are unknown.
+pub fn matches_paths(config: &WebhookConfig, value: &Value) -> Result<bool, Error> {
+    if config.paths.is_empty() && config.paths_ignore.is_empty() {
+        return Ok(true);
+    }
+    let Some(files) = changed_files(value) else {
+        return Ok(true);
+    };
+
+    let paths = Patterns::new(&config.paths)?;
+    let paths_ignore = Patterns::new(&config.paths_ignore)?;
+    Ok(files.into_iter().any(|file| {
+        (config.paths.is_empty() || paths.matches(file)) && !paths_ignore.matches(file)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use grhooks_config::WebhookConfig;
+    use serde_json::{Value, json};
+
+    use super::matches_paths;
+
+    fn push(files: &[&str]) -> Value {
+        json!({
+            "commits": [
+                {"added": [], "modified": files.first().into_iter().collect::<Vec<_>>(), "removed": []},
+                {"added": files.iter().skip(1).collect::<Vec<_>>()},
+            ]
+        })
+    }
+
+    fn matches(paths: &[&str], paths_ignore: &[&str], files: &[&str]) -> bool {
+        let config: WebhookConfig = serde_json::from_value(json!({
+            "path": "deploy",
+            "events": ["push"],
+            "paths": paths,
+            "paths_ignore": paths_ignore,
+        }))
+        .unwrap();
+        matches_paths(&config, &push(files)).unwrap()
+    }
+
+    #[test]
+    fn no_filters_match_everything() {
+        assert!(matches(&[], &[], &[]));
+        assert!(matches(&[], &[], &["README.md"]));
+    }
+
+    #[test]
+    fn paths_need_a_matching_file() {
+        assert!(matches(&["src/**"], &[], &["README.md", "src/main.rs"]));
+        assert!(!matches(&["src/**"], &[], &["README.md", "docs/src/a.md"]));
+        assert!(!matches(&["src/**"], &[], &[]));
+    }
+
+    #[test]
+    fn wildcards_stop_at_separators() {
+        assert!(matches(&["*.md"], &[], &["README.md"]));
+        assert!(!matches(&["*.md"], &[], &["docs/guide.md"]));
+        assert!(matches(&["**/*.md"], &[], &["docs/guide.md"]));
+    }
+
+    #[test]
+    fn paths_ignore_skips_only_ignored_files() {
+        assert!(!matches(
+            &[],
+            &["docs/**", "*.md"],
+            &["README.md", "docs/a.txt"]
+        ));
+        assert!(matches(&[], &["docs/**"], &["docs/a.md", "src/lib.rs"]));
+    }
+
+    #[test]
+    fn later_negations_exclude_earlier_patterns() {
+        let paths = ["src/**", "!src/**/*_test.rs"];
+        assert!(matches(&paths, &[], &["src/lib.rs"]));
+        assert!(!matches(&paths, &[], &["src/cmd/run_test.rs"]));
+        // A later pattern includes the file again
+        let paths = ["src/**", "!src/generated/**", "src/generated/keep.rs"];
+        assert!(!matches(&paths, &[], &["src/generated/a.rs"]));
+        assert!(matches(&paths, &[], &["src/generated/keep.rs"]));
+    }
+
+    #[test]
+    fn negations_in_paths_ignore_keep_files() {
+        let ignore = ["docs/**", "!docs/api/**"];
+        assert!(!matches(&[], &ignore, &["docs/guide.md"]));
+        assert!(matches(&[], &ignore, &["docs/api/index.md"]));
+    }
+
+    #[test]
+    fn paths_and_paths_ignore_combine() {
+        assert!(!matches(&["src/**"], &["**/*.md"], &["src/README.md"]));
+        assert!(matches(
+            &["src/**"],
+            &["**/*.md"],
+            &["src/README.md", "src/lib.rs"]
+        ));
+    }
+
+    #[test]
+    fn payloads_without_commits_are_not_filtered() {
+        let config: WebhookConfig =
+            serde_json::from_value(json!({"path": "x", "events": ["*"], "paths": ["src/**"]}))
+                .unwrap();
+        assert!(matches_paths(&config, &json!({"action": "opened"})).unwrap());
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let config: WebhookConfig =
+            serde_json::from_value(json!({"path": "x", "events": ["*"], "paths": ["src/[a"]}))
+                .unwrap();
+        assert!(matches_paths(&config, &push(&["src/a"])).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+use crate::Redactor;
+
+/// Output of a child process, possibly partial if it was terminated
+pub(crate) struct ProcessOutput {
+    /// `None` when the process was terminated after its timeout
+    pub status: Option<ExitStatus>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A redacted line written by a running command
+#[derive(Clone, Debug)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub line: String,
+}
+
+/// Where the lines of a running command are sent, redacted
+#[derive(Clone)]
+pub(crate) struct LineSink {
+    pub sender: UnboundedSender<OutputLine>,
+    pub redactor: Redactor,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadline {
+    pub timeout: Option<Duration>,
+    /// Time between `SIGTERM` and `SIGKILL` once the timeout expired
+    pub grace: Duration,
+}
+
+/// Spawns the command in its own process group and waits for it within the deadline
+pub(crate) async fn run(
+    mut command: Command,
+    deadline: Deadline,
+    sink: Option<&LineSink>,
+    stdin: Option<Arc<[u8]>>,
+) -> std::io::Result<ProcessOutput> {
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = command.spawn()?;
+    // Kills the whole group if this future is dropped, e.g. when the job is cancelled
+    let mut group = ProcessGroup(child.id());
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), stdin) {
+        // Written in the background, the command may produce output before reading all of it
+        tokio::spawn(async move {
+            if let Err(e) = pipe.write_all(&input).await {
+                tracing::debug!("Command stopped reading its input: {e}");
+            }
+        });
+    }
+    let stdout = Collector::spawn(child.stdout.take(), OutputStream::Stdout, sink.cloned());
+    let stderr = Collector::spawn(child.stderr.take(), OutputStream::Stderr, sink.cloned());
+
+    let status = match deadline.timeout {
+        Some(timeout) => {
+            if let Ok(status) = tokio::time::timeout(timeout, child.wait()).await {
+                Some(status?)
+            } else {
+                tracing::warn!("Command timed out after {timeout:?}, terminating it");
+                terminate(&mut child, &group, deadline.grace).await;
+                None
+            }
+        }
+        None => Some(child.wait().await?),
+    };
+    group.0 = None;
+
+    Ok(ProcessOutput {
+        status,
+        stdout: stdout.finish(deadline.grace).await,
+        stderr: stderr.finish(deadline.grace).await,
+    })
+}
+
+async fn terminate(child: &mut Child, group: &ProcessGroup, grace: Duration) {
+    #[cfg(unix)]
+    {
+        group.signal(libc::SIGTERM);
+        if tokio::time::timeout(grace, child.wait()).await.is_err() {
+            tracing::warn!("Command ignored SIGTERM for {grace:?}, killing it");
+        }
+        // Also takes care of children left behind by the process group leader
+        group.signal(libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    {
+        _ = (group, grace);
+        _ = child.start_kill();
+    }
+    _ = child.wait().await;
+}
+
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    #[cfg(unix)]
+    fn signal(&self, signal: libc::c_int) {
+        let Some(pid) = self.0.and_then(|pid| libc::pid_t::try_from(pid).ok()) else {
+            return;
+        };
+        // SAFETY: `kill` has no memory safety requirements, a negative pid targets the group
+        unsafe {
+            libc::kill(-pid, signal);
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        self.signal(libc::SIGKILL);
+    }
+}
+
+/// Reads a pipe in the background, keeping what was read even if it never closes
+struct Collector {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Collector {
+    fn spawn(
+        pipe: Option<impl AsyncRead + Unpin + Send + 'static>,
+        stream: OutputStream,
+        sink: Option<LineSink>,
+    ) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let task = pipe.map(|mut pipe| {
+            let buffer = buffer.clone();
+            tokio::spawn(async move {
+                let mut chunk = [0; 4096];
+                let mut line = Vec::new();
+                while let Ok(read @ 1..) = pipe.read(&mut chunk).await {
+                    buffer.lock().unwrap().extend_from_slice(&chunk[..read]);
+                    let Some(sink) = &sink else {
+                        continue;
+                    };
+                    for &byte in &chunk[..read] {
+                        if byte == b'\n' {
+                            sink.send(stream, &std::mem::take(&mut line));
+                        } else {
+                            line.push(byte);
+                        }
+                    }
+                }
+                if let Some(sink) = sink.filter(|_| !line.is_empty()) {
+                    sink.send(stream, &line);
+                }
+            })
+        });
+
+        Self { buffer, task }
+    }
+
+    async fn finish(mut self, grace: Duration) -> Vec<u8> {
+        if let Some(mut task) = self.task.take() {
+            // Background processes may keep the pipe open after the command exited
+            if tokio::time::timeout(grace, &mut task).await.is_err() {
+                task.abort();
+            }
+        }
+        std::mem::take(&mut *self.buffer.lock().unwrap())
+    }
+}
+
+impl LineSink {
+    fn send(&self, stream: OutputStream, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        // Nobody listening is not an error, the output is still collected
+        _ = self.sender.send(OutputLine {
+            stream,
+            line: self.redactor.redact(line).into_owned(),
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::process::Command;
+
+    use super::{Deadline, run};
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    /// Whether the process still runs, zombies waiting for a reaper excluded
+    fn is_running(pid: &str) -> bool {
+        let output = std::process::Command::new("ps")
+            .args(["-o", "stat=", "-p", pid])
+            .output()
+            .unwrap();
+        let stat = String::from_utf8_lossy(&output.stdout);
+        !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+    }
+
+    #[tokio::test]
+    async fn finished_commands_keep_their_status() {
+        let deadline = Deadline {
+            timeout: Some(Duration::from_secs(10)),
+            grace: Duration::from_secs(1),
+        };
+        let output = run(
+            shell("echo out; echo err >&2; exit 3"),
+            deadline,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.status.and_then(|status| status.code()), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[tokio::test]
+    async fn timeouts_kill_the_whole_group_and_keep_partial_output() {
+        let deadline = Deadline {
+            timeout: Some(Duration::from_secs(1)),
+            grace: Duration::from_millis(200),
+        };
+        let started = Instant::now();
+        let output = run(shell("sleep 10 & echo $!; sleep 10"), deadline, None, None)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(output.status.is_none());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let background = stdout.trim();
+        assert_ne!(background, "");
+        for _ in 0..50 {
+            if !is_running(background) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("background process {background} survived the timeout");
+    }
+
+    #[tokio::test]
+    async fn commands_ignoring_sigterm_are_killed_after_the_grace() {
+        let deadline = Deadline {
+            timeout: Some(Duration::from_millis(500)),
+            grace: Duration::from_millis(500),
+        };
+        let started = Instant::now();
+        let output = run(
+            shell("trap '' TERM; echo ready; sleep 10"),
+            deadline,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let elapsed = started.elapsed();
+        assert!(output.status.is_none());
+        assert_eq!(output.stdout, b"ready\n");
+        assert!(elapsed >= Duration::from_secs(1), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+}
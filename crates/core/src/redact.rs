@@ -0,0 +1,43 @@
+use std::borrow::Cow;
+
+use grhooks_config::WebhookConfig;
+
+const REDACTED: &str = "***";
+
+/// Scrubs the secrets configured on a webhook from any text
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    #[must_use]
+    pub fn new(config: &WebhookConfig, event_type: &str) -> Self {
+        let mut secrets = config
+            .secret
+            .iter()
+            .map(|secret| {
+                crate::render_secret(secret, event_type)
+                    .expose()
+                    .to_string()
+            })
+            .filter(|secret| !secret.is_empty())
+            .collect::<Vec<_>>();
+        // Longer secrets first, so a secret containing another one is fully scrubbed
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        secrets.dedup();
+
+        Self { secrets }
+    }
+
+    #[must_use]
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = Cow::Owned(text.replace(secret.as_str(), REDACTED));
+            }
+        }
+        text
+    }
+}
@@ -0,0 +1,95 @@
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use grhooks_config::RetryConfig;
+
+use crate::Error;
+
+pub(crate) fn is_retryable(retry: &RetryConfig, error: &Error) -> bool {
+    match error {
+        Error::Failed { exit_code, .. } => {
+            retry.exit_codes.is_empty()
+                || exit_code.is_some_and(|code| retry.exit_codes.contains(&code))
+        }
+        _ => false,
+    }
+}
+
+/// Exponential backoff for the given failed attempt, with up to 50% of random jitter
+pub(crate) fn backoff(retry: &RetryConfig, attempt: u32) -> Duration {
+    let delay = Duration::from_secs(retry.backoff)
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(Duration::from_secs(retry.max_backoff));
+
+    let jitter = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    #[allow(clippy::cast_precision_loss)]
+    let factor = 0.5 + (jitter as f64 / u64::MAX as f64) / 2.0;
+
+    delay.mul_f64(factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use grhooks_config::RetryConfig;
+
+    use super::{backoff, is_retryable};
+    use crate::Error;
+
+    fn retry(exit_codes: Vec<i32>) -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            backoff: 2,
+            max_backoff: 60,
+            exit_codes,
+        }
+    }
+
+    fn failed(exit_code: Option<i32>) -> Error {
+        Error::Failed {
+            exit_code,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn any_failure_is_retried_without_exit_codes() {
+        let retry = retry(Vec::new());
+        assert!(is_retryable(&retry, &failed(Some(1))));
+        assert!(is_retryable(&retry, &failed(None)));
+        assert!(!is_retryable(&retry, &Error::Cancelled));
+        let timed_out = Error::TimedOut {
+            message: String::new(),
+        };
+        assert!(!is_retryable(&retry, &timed_out));
+    }
+
+    #[test]
+    fn only_listed_exit_codes_are_retried() {
+        let retry = retry(vec![75, 111]);
+        assert!(is_retryable(&retry, &failed(Some(75))));
+        assert!(is_retryable(&retry, &failed(Some(111))));
+        assert!(!is_retryable(&retry, &failed(Some(1))));
+        // Killed by a signal
+        assert!(!is_retryable(&retry, &failed(None)));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_with_jitter() {
+        let retry = retry(Vec::new());
+        for (attempt, seconds) in [(1, 2), (2, 4), (3, 8), (5, 32), (6, 60), (40, 60)] {
+            let delay = Duration::from_secs(seconds);
+            for _ in 0..100 {
+                let wait = backoff(&retry, attempt);
+                assert!(
+                    wait >= delay / 2 && wait <= delay,
+                    "attempt {attempt}: {wait:?} outside of [{:?}, {delay:?}]",
+                    delay / 2
+                );
+            }
+        }
+    }
+}
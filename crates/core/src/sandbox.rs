@@ -0,0 +1,390 @@
+//! Resource limits and restrictions of commands, applied between `fork` and `exec`
+
+use std::path::Path;
+
+use grhooks_config::{LimitsConfig, SandboxConfig, WebhookConfig};
+use tokio::process::Command;
+
+use crate::Error;
+use crate::user::Credentials;
+
+/// Hardening of the commands of a webhook, from its `limits` and `sandbox`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) struct Sandbox<'a> {
+    limits: Option<&'a LimitsConfig>,
+    config: Option<&'a SandboxConfig>,
+    /// Identity of the command, mapped to itself in a new user namespace
+    uid: u32,
+    gid: u32,
+    /// ABI version of Landlock, when the webhook restricts paths
+    landlock: Option<i32>,
+    /// Readable by the command whatever its paths are
+    payload_file: Option<&'a Path>,
+}
+
+impl<'a> Sandbox<'a> {
+    /// `None` when the webhook has neither `limits` nor `sandbox`
+    #[cfg(target_os = "linux")]
+    pub fn new(
+        config: &'a WebhookConfig,
+        credentials: Option<&Credentials>,
+        payload_file: Option<&'a Path>,
+    ) -> Result<Option<Self>, Error> {
+        use grhooks_config::Namespace;
+
+        if config.limits.is_none() && config.sandbox.is_none() {
+            return Ok(None);
+        }
+
+        // SAFETY: always successful
+        let uid = credentials
+            .and_then(|credentials| credentials.uid)
+            .unwrap_or_else(|| unsafe { libc::geteuid() });
+        let gid = credentials
+            .and_then(|credentials| credentials.gid)
+            .unwrap_or_else(|| unsafe { libc::getegid() });
+
+        let mut landlock = None;
+        if let Some(sandbox) = &config.sandbox {
+            // The capabilities of root are dropped along with its uid, before the namespaces are created
+            if !sandbox.namespaces.is_empty()
+                && !sandbox.namespaces.contains(&Namespace::User)
+                && uid != 0
+            {
+                return Err(Error::Sandbox(
+                    "mount and network namespaces need root or a user namespace".to_string(),
+                ));
+            }
+            if !sandbox.read_only.is_empty() || !sandbox.read_write.is_empty() {
+                let abi = landlock::abi().map_err(|e| {
+                    Error::Sandbox(format!("Landlock is not available in this kernel: {e}"))
+                })?;
+                landlock = Some(abi);
+            }
+        }
+
+        Ok(Some(Self {
+            limits: config.limits.as_ref(),
+            config: config.sandbox.as_ref(),
+            uid,
+            gid,
+            landlock,
+            payload_file,
+        }))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(
+        config: &'a WebhookConfig,
+        _credentials: Option<&Credentials>,
+        _payload_file: Option<&'a Path>,
+    ) -> Result<Option<Self>, Error> {
+        if config.limits.is_some() || config.sandbox.is_some() {
+            return Err(Error::Sandbox("only supported on Linux".to_string()));
+        }
+        Ok(None)
+    }
+
+    /// Restricts the command once spawned, `script` being readable by it like the payload file
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, command: &mut Command, script: Option<&Path>) -> Result<(), Error> {
+        use grhooks_config::Namespace;
+
+        let mut restrictions = Restrictions {
+            namespaces: 0,
+            id_maps: None,
+            limits: self.limits.cloned().unwrap_or_default(),
+            no_new_privs: false,
+            ruleset: None,
+        };
+
+        if let Some(config) = self.config {
+            for namespace in &config.namespaces {
+                restrictions.namespaces |= match namespace {
+                    Namespace::User => libc::CLONE_NEWUSER,
+                    Namespace::Mount => libc::CLONE_NEWNS,
+                    Namespace::Network => libc::CLONE_NEWNET,
+                };
+            }
+            if restrictions.namespaces & libc::CLONE_NEWUSER != 0 {
+                restrictions.id_maps = Some((
+                    format!("{0} {0} 1", self.uid).into_bytes(),
+                    format!("{0} {0} 1", self.gid).into_bytes(),
+                ));
+            }
+            restrictions.no_new_privs = config.no_new_privs;
+
+            if let Some(abi) = self.landlock {
+                let ruleset = landlock::Ruleset::new(abi)
+                    .map_err(|e| Error::Sandbox(format!("cannot create Landlock ruleset: {e}")))?;
+                let paths = (config.read_only.iter().map(|path| (path.as_path(), false)))
+                    .chain(config.read_write.iter().map(|path| (path.as_path(), true)))
+                    .chain(
+                        self.payload_file
+                            .into_iter()
+                            .chain(script)
+                            .map(|path| (path, false)),
+                    );
+                for (path, writable) in paths {
+                    match ruleset.allow(path, writable) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                            tracing::debug!("Not allowing missing path {path:?} in the sandbox");
+                        }
+                        Err(e) => {
+                            return Err(Error::Sandbox(format!(
+                                "cannot allow {}: {e}",
+                                path.display()
+                            )));
+                        }
+                    }
+                }
+                restrictions.ruleset = Some(ruleset);
+            }
+        }
+
+        // SAFETY: the closure only makes async-signal-safe calls, without allocating
+        unsafe {
+            command.pre_exec(move || restrictions.apply());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _command: &mut Command, _script: Option<&Path>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Everything set up in the child, prepared by the parent so nothing is allocated after `fork`
+#[cfg(target_os = "linux")]
+struct Restrictions {
+    namespaces: libc::c_int,
+    /// Contents of `uid_map` and `gid_map` in a new user namespace
+    id_maps: Option<(Vec<u8>, Vec<u8>)>,
+    limits: LimitsConfig,
+    no_new_privs: bool,
+    ruleset: Option<landlock::Ruleset>,
+}
+
+#[cfg(target_os = "linux")]
+impl Restrictions {
+    /// Runs in the child, after it switched to the user and group of the webhook
+    fn apply(&self) -> std::io::Result<()> {
+        use std::io::Error;
+
+        if self.namespaces != 0 {
+            // SAFETY: plain syscalls on constant arguments
+            unsafe {
+                if libc::unshare(self.namespaces) != 0 {
+                    return Err(Error::last_os_error());
+                }
+                if let Some((uid_map, gid_map)) = &self.id_maps {
+                    // Switching users made the child undumpable, leaving `/proc/self` to root
+                    // until `exec`, which restores the flag
+                    libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0);
+                    let written = write_file(c"/proc/self/setgroups", b"deny")
+                        .and_then(|()| write_file(c"/proc/self/uid_map", uid_map))
+                        .and_then(|()| write_file(c"/proc/self/gid_map", gid_map));
+                    libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+                    written?;
+                }
+                // Keeps mounts made by the command from propagating back to the host
+                if self.namespaces & libc::CLONE_NEWNS != 0
+                    && libc::mount(
+                        std::ptr::null(),
+                        c"/".as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_REC | libc::MS_PRIVATE,
+                        std::ptr::null(),
+                    ) != 0
+                {
+                    return Err(Error::last_os_error());
+                }
+            }
+        }
+
+        let mib = |value: u64| value.saturating_mul(1024 * 1024);
+        let limits = [
+            // `SIGXCPU` at the limit, then `SIGKILL` a second later
+            (libc::RLIMIT_CPU, self.limits.cpu, 1),
+            (libc::RLIMIT_AS, self.limits.memory.map(mib), 0),
+            (libc::RLIMIT_NOFILE, self.limits.open_files, 0),
+            (libc::RLIMIT_NPROC, self.limits.processes, 0),
+        ];
+        for (resource, limit, extra) in limits {
+            let Some(limit) = limit else { continue };
+            let limit = libc::rlimit {
+                rlim_cur: limit,
+                rlim_max: limit.saturating_add(extra),
+            };
+            // SAFETY: `limit` is a valid `rlimit`
+            if unsafe { libc::setrlimit(resource, &raw const limit) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        // Landlock requires it of processes without `CAP_SYS_ADMIN`
+        if self.no_new_privs || self.ruleset.is_some() {
+            // SAFETY: plain syscall on constant arguments
+            if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if let Some(ruleset) = &self.ruleset {
+            ruleset.restrict_self()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a small file with raw syscalls, as allowed between `fork` and `exec`
+#[cfg(target_os = "linux")]
+fn write_file(path: &std::ffi::CStr, content: &[u8]) -> std::io::Result<()> {
+    // SAFETY: `path` is nul-terminated and `content` valid for its length
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, content.as_ptr().cast(), content.len());
+        let error = std::io::Error::last_os_error();
+        libc::close(fd);
+        if written < 0 { Err(error) } else { Ok(()) }
+    }
+}
+
+/// Minimal bindings to the Landlock syscalls, which have no wrapper in libc
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+    /// Rights of read-only paths
+    const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+    /// Rights that can be granted on a file rather than a directory
+    const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_TRUNCATE
+        | ACCESS_FS_IOCTL_DEV;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// ABI version supported by the kernel
+    pub fn abi() -> io::Result<i32> {
+        // SAFETY: querying the version takes no attributes
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if version < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(i32::try_from(version).unwrap_or(i32::MAX))
+    }
+
+    /// Filesystem rights denied outside of the allowed paths
+    pub struct Ruleset {
+        fd: OwnedFd,
+        handled: u64,
+    }
+
+    impl Ruleset {
+        pub fn new(abi: i32) -> io::Result<Self> {
+            // Every right known to the kernel: `REFER` came in v2, `TRUNCATE` in v3, `IOCTL_DEV` in v5
+            let handled = match abi {
+                ..=1 => (1 << 13) - 1,
+                2 => (1 << 14) - 1,
+                3 | 4 => (1 << 15) - 1,
+                _ => (1 << 16) - 1,
+            };
+            let attr = RulesetAttr {
+                handled_access_fs: handled,
+            };
+            // SAFETY: `attr` is valid for its size
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    &raw const attr,
+                    size_of::<RulesetAttr>(),
+                    0,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the syscall returned a new file descriptor
+            #[allow(clippy::cast_possible_truncation)]
+            let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+            Ok(Self { fd, handled })
+        }
+
+        /// Allows reading and executing beneath `path`, and modifying it when `writable`
+        pub fn allow(&self, path: &Path, writable: bool) -> io::Result<()> {
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+                .open(path)?;
+            let mut access = if writable { u64::MAX } else { ACCESS_READ };
+            if !file.metadata()?.is_dir() {
+                access &= ACCESS_FILE;
+            }
+            let attr = PathBeneathAttr {
+                allowed_access: access & self.handled,
+                parent_fd: file.as_raw_fd(),
+            };
+            // SAFETY: `attr` is valid for the duration of the call
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    self.fd.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &raw const attr,
+                    0,
+                )
+            };
+            if result < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// Enforces the ruleset on the calling process, async-signal-safe
+        pub fn restrict_self(&self) -> io::Result<()> {
+            // SAFETY: plain syscall on a valid ruleset
+            if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, self.fd.as_raw_fd(), 0) }
+                < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Keeps payload values from being interpreted by the shell running the command
+
+use std::borrow::Cow;
+
+use grhooks_config::{Interpolation, WebhookConfig};
+use serde_json::Value;
+use srtemplate::SrTemplate;
+use srtemplate::function::FuncResult;
+
+use crate::env::{Exporter, env_name};
+use crate::{Error, Redactor};
+
+/// Template variables of a command, and the payload variables exported to its environment
+pub(crate) struct CommandTemplate {
+    pub ctx: SrTemplate<'static>,
+    pub env: Vec<(String, String)>,
+    interpolation: Interpolation,
+}
+
+impl CommandTemplate {
+    pub fn new(
+        config: &WebhookConfig,
+        redactor: &Redactor,
+        event_type: &str,
+        value: &Value,
+        source: &str,
+    ) -> Result<Self, Error> {
+        let mut template = Self {
+            ctx: SrTemplate::with_delimiter("${{", "}}"),
+            env: Vec::new(),
+            interpolation: config.interpolation,
+        };
+        template.ctx.add_function("raw", raw);
+        let exporter = Exporter::new(config, source)?;
+
+        let variables = std::iter::once(("event.type".to_string(), event_type.to_string()))
+            .chain(crate::flatten("event", value))
+            .map(|(name, value)| {
+                let var = env_name(&name);
+                (name, var, value)
+            })
+            .collect::<Vec<_>>();
+        let exports = exporter.map_or_else(
+            || vec![false; variables.len()],
+            |exporter| exporter.select(&variables),
+        );
+        for ((name, var, value), export) in variables.into_iter().zip(exports) {
+            tracing::trace!("Processing value: {name} = {}", redactor.redact(&value));
+            template.add_variable(name, var, value, export);
+        }
+
+        Ok(template)
+    }
+
+    /// Adds a variable holding `value`, exported to the environment as `var` when `export` is set
+    pub fn add_variable(&mut self, name: String, var: String, value: String, export: bool) {
+        match self.interpolation {
+            Interpolation::Quote => self.ctx.add_variable(name, quote(&value)),
+            Interpolation::Env => self.ctx.add_variable(name, format!("\"${var}\"")),
+        }
+        if export {
+            self.env.push((var, value));
+        }
+    }
+}
+
+/// Quotes a value as a single shell word, plain words are left as they are
+fn quote(value: &str) -> Cow<'_, str> {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if plain {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(format!("'{}'", value.replace('\'', r"'\''")))
+    }
+}
+
+/// `raw(...)` template function, splicing values without the quotes added by [`quote`].
+///
+/// In `env` interpolation the variable reference is kept, so values never reach the command text.
+#[allow(clippy::unnecessary_wraps)] // srtemplate functions return a `FuncResult`
+fn raw(args: &[String]) -> FuncResult {
+    Ok(args
+        .iter()
+        .map(|arg| {
+            if let Some(quoted) = arg
+                .strip_prefix('\'')
+                .and_then(|arg| arg.strip_suffix('\''))
+            {
+                Cow::Owned(quoted.replace(r"'\''", "'"))
+            } else if let Some(reference) = arg
+                .strip_prefix('"')
+                .and_then(|arg| arg.strip_suffix('"'))
+                .filter(|arg| arg.starts_with("$GRHOOKS_"))
+            {
+                Cow::Borrowed(reference)
+            } else {
+                Cow::Borrowed(arg.as_str())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, raw};
+
+    fn raw_of(value: &str) -> String {
+        raw(&[value.to_string()]).unwrap()
+    }
+
+    #[test]
+    fn plain_values_are_not_quoted() {
+        assert_eq!(quote("refs/heads/main"), "refs/heads/main");
+        assert_eq!(quote("user@example.com"), "user@example.com");
+        assert_eq!(quote("42"), "42");
+    }
+
+    #[test]
+    fn special_values_are_single_quoted() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("$(id -u) x"), "'$(id -u) x'");
+        assert_eq!(quote("`id`; rm -rf /"), "'`id`; rm -rf /'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_reads_quoted_values_back() {
+        for value in ["", "$(id -u) x", "it's", "a\nb", "'\"\\$HOME'"] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", quote(value)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), value);
+        }
+    }
+
+    #[test]
+    fn raw_undoes_quote() {
+        for value in ["", "$(id -u) x", "it's", "''", "refs/heads/main"] {
+            assert_eq!(raw_of(&quote(value)), value);
+        }
+    }
+
+    #[test]
+    fn raw_keeps_env_references() {
+        assert_eq!(raw_of("\"$GRHOOKS_EVENT_REF\""), "$GRHOOKS_EVENT_REF");
+        assert_eq!(raw_of("\"quoted\""), "\"quoted\"");
+    }
+
+    #[test]
+    fn raw_joins_arguments() {
+        let args = ["'a b'".to_string(), "c".to_string()];
+        assert_eq!(raw(&args).unwrap(), "a b c");
+    }
+}
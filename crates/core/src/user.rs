@@ -0,0 +1,178 @@
+//! User and group commands run as, when grhooks runs as root
+
+use grhooks_config::WebhookConfig;
+
+use crate::Error;
+
+/// Identity a command switches to before it starts
+pub(crate) struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// `HOME`, `USER` and `LOGNAME` of the user
+    pub env: Vec<(&'static str, String)>,
+}
+
+impl Credentials {
+    /// `None` when the webhook runs its command as grhooks itself
+    #[cfg(unix)]
+    pub fn resolve(config: &WebhookConfig) -> Result<Option<Self>, Error> {
+        if config.user.is_none() && config.group.is_none() {
+            return Ok(None);
+        }
+
+        let gid = match &config.group {
+            Some(group) => Some(
+                group
+                    .parse()
+                    .ok()
+                    .or_else(|| unix::group(group))
+                    .ok_or_else(|| Error::User(format!("unknown group {group:?}")))?,
+            ),
+            None => None,
+        };
+
+        let Some(user) = &config.user else {
+            return Ok(Some(Self {
+                uid: None,
+                gid,
+                env: Vec::new(),
+            }));
+        };
+
+        match unix::user(user) {
+            Some(passwd) => Ok(Some(Self {
+                uid: Some(passwd.uid),
+                gid: gid.or(Some(passwd.gid)),
+                env: vec![
+                    ("HOME", passwd.home),
+                    ("USER", passwd.name.clone()),
+                    ("LOGNAME", passwd.name),
+                ],
+            })),
+            // Users missing from the passwd database need an explicit group
+            None => match (user.parse(), gid) {
+                (Ok(uid), Some(gid)) => Ok(Some(Self {
+                    uid: Some(uid),
+                    gid: Some(gid),
+                    env: Vec::new(),
+                })),
+                (Ok(_), None) => Err(Error::User(format!("user {user:?} needs a group"))),
+                (Err(_), _) => Err(Error::User(format!("unknown user {user:?}"))),
+            },
+        }
+    }
+
+    /// Runs the command as the user, with its environment
+    pub fn apply(&self, command: &mut tokio::process::Command) {
+        command.envs(self.env.iter().cloned());
+        #[cfg(unix)]
+        {
+            if let Some(uid) = self.uid {
+                command.uid(uid);
+            }
+            if let Some(gid) = self.gid {
+                command.gid(gid);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn resolve(config: &WebhookConfig) -> Result<Option<Self>, Error> {
+        if config.user.is_some() || config.group.is_some() {
+            return Err(Error::User("not supported on this platform".to_string()));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::{CStr, CString, c_char};
+
+    pub struct Passwd {
+        pub uid: u32,
+        pub gid: u32,
+        pub name: String,
+        pub home: String,
+    }
+
+    /// Calls a reentrant lookup such as `getpwnam_r`, growing its buffer until the entry fits.
+    ///
+    /// The strings of the entry live in the buffer, so they are read by `convert` before it is freed.
+    fn lookup<T, R>(
+        mut call: impl FnMut(&mut T, &mut [c_char], &mut *mut T) -> i32,
+        convert: impl FnOnce(&T) -> R,
+    ) -> Option<R> {
+        let mut buffer = vec![0; 1024];
+        loop {
+            // SAFETY: `passwd` and `group` are plain C structs, valid when zeroed
+            let mut entry = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            match call(&mut entry, &mut buffer, &mut result) {
+                libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+                0 if !result.is_null() => return Some(convert(&entry)),
+                _ => return None,
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must point to a nul-terminated string
+    unsafe fn string(ptr: *const c_char) -> String {
+        // SAFETY: guaranteed by the caller
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Looks a user up by name, or by uid when numeric
+    pub fn user(user: &str) -> Option<Passwd> {
+        let name = CString::new(user).ok()?;
+        let uid = user.parse::<libc::uid_t>().ok();
+        lookup(
+            |entry, buffer, result| match uid {
+                // SAFETY: every pointer is valid for the duration of the call, with the buffer length
+                Some(uid) => unsafe {
+                    libc::getpwuid_r(uid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+                },
+                None => unsafe {
+                    libc::getpwnam_r(
+                        name.as_ptr(),
+                        entry,
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                        result,
+                    )
+                },
+            },
+            |passwd: &libc::passwd| Passwd {
+                uid: passwd.pw_uid,
+                gid: passwd.pw_gid,
+                // SAFETY: a successful lookup fills the entry with nul-terminated strings
+                name: unsafe { string(passwd.pw_name) },
+                home: unsafe { string(passwd.pw_dir) },
+            },
+        )
+    }
+
+    /// Looks the gid of a group up by name
+    pub fn group(group: &str) -> Option<u32> {
+        let name = CString::new(group).ok()?;
+        lookup(
+            |entry, buffer, result| {
+                // SAFETY: every pointer is valid for the duration of the call, with the buffer length
+                unsafe {
+                    libc::getgrnam_r(
+                        name.as_ptr(),
+                        entry,
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                        result,
+                    )
+                }
+            },
+            |group: &libc::group| group.gr_gid,
+        )
+    }
+}
@@ -0,0 +1,138 @@
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::{Error, WebhookOrigin};
+
+/// Validates deliveries from both Bitbucket Cloud and Bitbucket Data Center (Server)
+pub struct BitbucketValidator;
+
+impl WebhookOrigin for BitbucketValidator {
+    fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
+        if !headers.contains_key("X-Event-Key") {
+            return Err(Error::MissingHeader("X-Event-Key"));
+        }
+
+        // Bitbucket Cloud identifies the hook with `X-Hook-UUID`, while
+        // Bitbucket Data Center only sends `X-Request-Id`
+        if !headers.contains_key("X-Hook-UUID") && !headers.contains_key("X-Request-Id") {
+            return Err(Error::MissingHeader("X-Hook-UUID"));
+        }
+
+        Ok(())
+    }
+
+    fn extract_event_type(&self, headers: &HeaderMap, _payload: &Value) -> Result<String, Error> {
+        headers
+            .get("X-Event-Key")
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+            .ok_or(Error::MissingHeader("X-Event-Key"))
+    }
+
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("X-Request-UUID")
+            .or_else(|| headers.get("X-Request-Id"))
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    }
+
+    fn validate_signature(
+        &self,
+        headers: &HeaderMap,
+        secret: &str,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let signature = headers
+            .get("X-Hub-Signature")
+            .and_then(|v| v.to_str().ok())
+            .ok_or(Error::MissingHeader("X-Hub-Signature"))?;
+
+        let (expected_signature, signature) =
+            if let Some(signature) = signature.strip_prefix("sha256=") {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                    .map_err(|_| Error::InvalidSignature)?;
+                mac.update(body);
+                (hex::encode(mac.finalize().into_bytes()), signature)
+            } else if let Some(signature) = signature.strip_prefix("sha1=") {
+                let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
+                    .map_err(|_| Error::InvalidSignature)?;
+                mac.update(body);
+                (hex::encode(mac.finalize().into_bytes()), signature)
+            } else {
+                return Err(Error::InvalidSignature);
+            };
+
+        if !constant_time_eq::constant_time_eq(signature.as_bytes(), expected_signature.as_bytes())
+        {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitbucketValidator;
+    use crate::{Error, WebhookOrigin, headers};
+
+    const BODY: &[u8] = br#"{"action":"opened"}"#;
+    const SHA256: &str = "sha256=7dd44a5aab54cf18c2c4dc925aa0123416e2b86b5fdeece15fdb45e8f10fd766";
+    const SHA1: &str = "sha1=d6d6dea467836eceef2a2a7e8b2af58e9364c516";
+
+    fn validate(signature: &str, secret: &str) -> Result<(), Error> {
+        BitbucketValidator.validate_signature(
+            &headers(&[("X-Hub-Signature", signature)]),
+            secret,
+            BODY,
+        )
+    }
+
+    #[test]
+    fn sha256_signatures_are_accepted() {
+        assert!(validate(SHA256, "mysecret").is_ok());
+        assert!(matches!(
+            validate(SHA256, "other"),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn sha1_signatures_are_accepted() {
+        assert!(validate(SHA1, "mysecret").is_ok());
+        assert!(matches!(
+            validate(SHA1, "other"),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn signatures_need_an_algorithm() {
+        let bare = SHA256.trim_start_matches("sha256=");
+        assert!(matches!(
+            validate(bare, "mysecret"),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            validate(&SHA1.replace("sha1=", "sha256="), "mysecret"),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn cloud_and_data_center_headers_are_accepted() {
+        let cloud = headers(&[("X-Event-Key", "repo:push"), ("X-Hook-UUID", "1")]);
+        let data_center = headers(&[("X-Event-Key", "repo:refs_changed"), ("X-Request-Id", "1")]);
+        assert!(BitbucketValidator.validate_headers(&cloud).is_ok());
+        assert!(BitbucketValidator.validate_headers(&data_center).is_ok());
+        assert!(
+            BitbucketValidator
+                .validate_headers(&headers(&[("X-Event-Key", "repo:push")]))
+                .is_err()
+        );
+    }
+}
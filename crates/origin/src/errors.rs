@@ -6,6 +6,7 @@ use axum::response::IntoResponse;
 pub enum Error {
     MissingHeader(&'static str),
     InvalidSignature,
+    InvalidTimestamp,
     InvalidUserAgent,
     UnsupportedEvent,
 }
@@ -15,6 +16,7 @@ impl Debug for Error {
         match self {
             Error::MissingHeader(header) => write!(f, "Missing required header: {header}"),
             Error::InvalidSignature => write!(f, "Invalid signature"),
+            Error::InvalidTimestamp => write!(f, "Invalid or expired timestamp"),
             Error::InvalidUserAgent => write!(f, "Invalid user agent"),
             Error::UnsupportedEvent => write!(f, "Unsupported event type"),
         }
@@ -32,6 +34,9 @@ impl IntoResponse for Error {
             Error::InvalidSignature => {
                 (StatusCode::BAD_REQUEST, "Invalid signature").into_response()
             }
+            Error::InvalidTimestamp => {
+                (StatusCode::BAD_REQUEST, "Invalid or expired timestamp").into_response()
+            }
             Error::InvalidUserAgent => {
                 (StatusCode::BAD_REQUEST, "Invalid user agent").into_response()
             }
@@ -0,0 +1,117 @@
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::{Error, WebhookOrigin};
+
+/// Validates deliveries from Gitea and its forks (Forgejo, Gogs)
+pub struct GiteaValidator;
+
+pub(crate) const EVENT_HEADERS: [&str; 3] = ["X-Forgejo-Event", "X-Gitea-Event", "X-Gogs-Event"];
+const DELIVERY_HEADERS: [&str; 3] = ["X-Forgejo-Delivery", "X-Gitea-Delivery", "X-Gogs-Delivery"];
+const SIGNATURE_HEADERS: [&str; 3] = [
+    "X-Forgejo-Signature",
+    "X-Gitea-Signature",
+    "X-Gogs-Signature",
+];
+
+fn first_header<'a>(headers: &'a HeaderMap, names: &[&'static str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|v| v.to_str().ok()))
+}
+
+impl WebhookOrigin for GiteaValidator {
+    fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
+        if first_header(headers, &EVENT_HEADERS).is_none() {
+            return Err(Error::MissingHeader("X-Gitea-Event"));
+        }
+
+        if first_header(headers, &DELIVERY_HEADERS).is_none() {
+            return Err(Error::MissingHeader("X-Gitea-Delivery"));
+        }
+
+        Ok(())
+    }
+
+    fn extract_event_type(&self, headers: &HeaderMap, _payload: &Value) -> Result<String, Error> {
+        first_header(headers, &EVENT_HEADERS)
+            .map(ToString::to_string)
+            .ok_or(Error::MissingHeader("X-Gitea-Event"))
+    }
+
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        first_header(headers, &DELIVERY_HEADERS).map(ToString::to_string)
+    }
+
+    fn extract_action(&self, payload: &Value) -> Option<String> {
+        payload["action"].as_str().map(ToString::to_string)
+    }
+
+    fn validate_signature(
+        &self,
+        headers: &HeaderMap,
+        secret: &str,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let signature = first_header(headers, &SIGNATURE_HEADERS)
+            .ok_or(Error::MissingHeader("X-Gitea-Signature"))?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .map_err(|_| Error::InvalidSignature)?;
+        mac.update(body);
+        let expected_signature = hex::encode(mac.finalize().into_bytes());
+
+        if !constant_time_eq::constant_time_eq(signature.as_bytes(), expected_signature.as_bytes())
+        {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GiteaValidator;
+    use crate::{Error, Origin, WebhookOrigin, headers};
+
+    const BODY: &[u8] = br#"{"action":"opened"}"#;
+    const SIGNATURE: &str = "7dd44a5aab54cf18c2c4dc925aa0123416e2b86b5fdeece15fdb45e8f10fd766";
+
+    #[test]
+    fn signature_headers_of_every_fork_are_accepted() {
+        for header in [
+            "X-Gitea-Signature",
+            "X-Forgejo-Signature",
+            "X-Gogs-Signature",
+        ] {
+            let delivery = headers(&[(header, SIGNATURE)]);
+            assert!(
+                GiteaValidator
+                    .validate_signature(&delivery, "mysecret", BODY)
+                    .is_ok()
+            );
+            assert!(matches!(
+                GiteaValidator.validate_signature(&delivery, "other", BODY),
+                Err(Error::InvalidSignature)
+            ));
+        }
+    }
+
+    #[test]
+    fn signatures_have_no_prefix() {
+        let delivery = headers(&[("X-Gitea-Signature", &format!("sha256={SIGNATURE}"))]);
+        assert!(matches!(
+            GiteaValidator.validate_signature(&delivery, "mysecret", BODY),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn gitea_is_detected_before_github() {
+        let delivery = headers(&[("X-GitHub-Event", "push"), ("X-Forgejo-Event", "push")]);
+        assert_eq!(Origin::try_from(&delivery).ok(), Some(Origin::Gitea));
+    }
+}
@@ -1,5 +1,6 @@
 use axum::http::HeaderMap;
 use hmac::{Hmac, Mac};
+use serde_json::Value;
 use sha1::Sha1;
 use sha2::Sha256;
 
@@ -34,7 +35,7 @@ impl WebhookOrigin for GitHubValidator {
         Ok(())
     }
 
-    fn extract_event_type(&self, headers: &HeaderMap) -> Result<String, Error> {
+    fn extract_event_type(&self, headers: &HeaderMap, _payload: &Value) -> Result<String, Error> {
         headers
             .get("X-GitHub-Event")
             .and_then(|v| v.to_str().ok())
@@ -42,6 +43,17 @@ impl WebhookOrigin for GitHubValidator {
             .ok_or(Error::MissingHeader("X-GitHub-Event"))
     }
 
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("X-GitHub-Delivery")
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    }
+
+    fn extract_action(&self, payload: &Value) -> Option<String> {
+        payload["action"].as_str().map(ToString::to_string)
+    }
+
     fn validate_signature(
         &self,
         headers: &HeaderMap,
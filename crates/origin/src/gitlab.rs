@@ -1,7 +1,22 @@
-use super::{Error, WebhookOrigin};
+use std::time::Duration;
+
 use axum::http::HeaderMap;
+use serde_json::Value;
+
+use super::{Error, WebhookOrigin, standard};
 
-pub struct GitLabValidator;
+pub struct GitLabValidator {
+    /// Timestamp tolerance of deliveries signed with a signing token
+    pub(crate) tolerance: Duration,
+}
+
+impl Default for GitLabValidator {
+    fn default() -> Self {
+        Self {
+            tolerance: standard::DEFAULT_TOLERANCE,
+        }
+    }
+}
 
 impl WebhookOrigin for GitLabValidator {
     fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
@@ -26,7 +41,7 @@ impl WebhookOrigin for GitLabValidator {
         Ok(())
     }
 
-    fn extract_event_type(&self, headers: &HeaderMap) -> Result<String, Error> {
+    fn extract_event_type(&self, headers: &HeaderMap, _payload: &Value) -> Result<String, Error> {
         headers
             .get("X-Gitlab-Event")
             .and_then(|v| v.to_str().ok())
@@ -34,17 +49,137 @@ impl WebhookOrigin for GitLabValidator {
             .ok_or(Error::MissingHeader("X-Gitlab-Event"))
     }
 
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("X-Gitlab-Event-UUID")
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    }
+
+    fn extract_action(&self, payload: &Value) -> Option<String> {
+        payload["object_attributes"]["action"]
+            .as_str()
+            .map(ToString::to_string)
+    }
+
     fn validate_signature(
         &self,
         headers: &HeaderMap,
-        _secret: &str,
-        _body: &[u8],
+        secret: &str,
+        body: &[u8],
     ) -> Result<(), Error> {
-        _ = headers
-            .get("X-Gitlab-Instance")
+        // Webhooks configured with a signing token send the HMAC headers
+        // instead of the plain secret token
+        if headers.contains_key("webhook-signature") {
+            return standard::validate_signature(headers, secret, body, self.tolerance);
+        }
+
+        let token = headers
+            .get("X-Gitlab-Token")
             .and_then(|v| v.to_str().ok())
-            .ok_or(Error::MissingHeader("X-Gitlab-Event-UUID"))?;
+            .ok_or(Error::MissingHeader("X-Gitlab-Token"))?;
+
+        if !constant_time_eq::constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+            return Err(Error::InvalidSignature);
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::GitLabValidator;
+    use crate::{Error, WebhookOrigin, headers};
+
+    const BODY: &[u8] = br#"{"object_kind":"push"}"#;
+
+    #[test]
+    fn secret_token_is_compared() {
+        let delivery = headers(&[("X-Gitlab-Token", "mysecret")]);
+        assert!(
+            GitLabValidator::default()
+                .validate_signature(&delivery, "mysecret", BODY)
+                .is_ok()
+        );
+        assert!(matches!(
+            GitLabValidator::default().validate_signature(&delivery, "other", BODY),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            GitLabValidator::default().validate_signature(&headers(&[]), "mysecret", BODY),
+            Err(Error::MissingHeader("X-Gitlab-Token"))
+        ));
+    }
+
+    #[test]
+    fn signing_token_uses_standard_webhooks() {
+        // Known-good signature from the Standard Webhooks specification, sent in 2021
+        let delivery = headers(&[
+            ("webhook-id", "msg_p5jXN8AQM9LWM0D4loKWxJek"),
+            ("webhook-timestamp", "1614265330"),
+            (
+                "webhook-signature",
+                "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=",
+            ),
+        ]);
+        assert!(matches!(
+            GitLabValidator::default().validate_signature(
+                &delivery,
+                "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw",
+                br#"{"test": 2432232314}"#
+            ),
+            Err(Error::InvalidTimestamp)
+        ));
+        assert!(
+            GitLabValidator {
+                tolerance: Duration::MAX
+            }
+            .validate_signature(
+                &delivery,
+                "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw",
+                br#"{"test": 2432232314}"#
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn user_agent_is_checked() {
+        let mut delivery = headers(&[
+            ("X-Gitlab-Event", "Push Hook"),
+            ("X-Gitlab-Webhook-UUID", "1"),
+            ("X-Gitlab-UUID", "2"),
+            ("User-Agent", "curl/8.5.0"),
+        ]);
+        assert!(matches!(
+            GitLabValidator::default().validate_headers(&delivery),
+            Err(Error::InvalidUserAgent)
+        ));
+        delivery.insert("User-Agent", "Gitlab/17.0.0".parse().unwrap());
+        assert!(
+            GitLabValidator::default()
+                .validate_headers(&delivery)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn action_comes_from_object_attributes() {
+        let payload = json!({"object_attributes": {"action": "open"}});
+        assert_eq!(
+            GitLabValidator::default()
+                .extract_action(&payload)
+                .as_deref(),
+            Some("open")
+        );
+        assert_eq!(
+            GitLabValidator::default().extract_action(&json!({"action": "x"})),
+            None
+        );
+    }
+}
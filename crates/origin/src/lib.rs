@@ -1,34 +1,71 @@
 #![allow(clippy::missing_errors_doc)]
 
+use std::time::Duration;
+
 use axum::http::HeaderMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 pub use crate::errors::Error;
 
+mod bitbucket;
 mod errors;
+mod gitea;
 mod github;
 mod gitlab;
+mod standard;
 mod webhook;
 
-#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub use standard::DEFAULT_TOLERANCE;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Origin {
     #[default]
     GitHub,
     GitLab,
+    Bitbucket,
+    #[serde(alias = "forgejo", alias = "gogs")]
+    Gitea,
     Webhook,
+    #[serde(rename = "standard", alias = "standard-webhooks")]
+    StandardWebhooks,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::GitHub => write!(f, "github"),
+            Origin::GitLab => write!(f, "gitlab"),
+            Origin::Bitbucket => write!(f, "bitbucket"),
+            Origin::Gitea => write!(f, "gitea"),
+            Origin::Webhook => write!(f, "webhook"),
+            Origin::StandardWebhooks => write!(f, "standard"),
+        }
+    }
 }
 
 impl<'a> TryFrom<&'a HeaderMap> for Origin {
     type Error = Error;
 
     fn try_from(headers: &'a HeaderMap) -> Result<Self, Self::Error> {
-        if headers.contains_key("X-GitHub-Event") {
+        // Forgejo and Gitea also send `X-GitHub-Event` for compatibility,
+        // so they must be detected before falling back to GitHub
+        if gitea::EVENT_HEADERS
+            .iter()
+            .any(|header| headers.contains_key(*header))
+        {
+            Ok(Origin::Gitea)
+        } else if headers.contains_key("X-GitHub-Event") {
             Ok(Origin::GitHub)
         } else if headers.contains_key("X-Gitlab-Event") {
             Ok(Origin::GitLab)
+        } else if headers.contains_key("X-Event-Key") {
+            Ok(Origin::Bitbucket)
         } else if headers.contains_key("X-Webhook-Event") {
             Ok(Origin::Webhook)
+        } else if headers.contains_key("webhook-id") && headers.contains_key("webhook-signature") {
+            Ok(Origin::StandardWebhooks)
         } else {
             Err(Self::Error::MissingHeader("X-*-Event"))
         }
@@ -37,7 +74,13 @@ impl<'a> TryFrom<&'a HeaderMap> for Origin {
 
 pub trait WebhookOrigin {
     fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error>;
-    fn extract_event_type(&self, headers: &HeaderMap) -> Result<String, Error>;
+    fn extract_event_type(&self, headers: &HeaderMap, payload: &Value) -> Result<String, Error>;
+    /// Unique identifier of the delivery, used to detect replayed requests
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String>;
+    /// Action of the event, such as `opened` for a `pull_request`, for `event.action` filters
+    fn extract_action(&self, _payload: &Value) -> Option<String> {
+        None
+    }
     fn validate_signature(
         &self,
         headers: &HeaderMap,
@@ -50,16 +93,54 @@ impl WebhookOrigin for Origin {
     fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
         match self {
             Origin::GitHub => github::GitHubValidator.validate_headers(headers),
-            Origin::GitLab => gitlab::GitLabValidator.validate_headers(headers),
+            Origin::GitLab => gitlab::GitLabValidator::default().validate_headers(headers),
+            Origin::Bitbucket => bitbucket::BitbucketValidator.validate_headers(headers),
+            Origin::Gitea => gitea::GiteaValidator.validate_headers(headers),
             Origin::Webhook => webhook::WebhookValidator.validate_headers(headers),
+            Origin::StandardWebhooks => {
+                standard::StandardWebhooksValidator::default().validate_headers(headers)
+            }
+        }
+    }
+
+    fn extract_event_type(&self, headers: &HeaderMap, payload: &Value) -> Result<String, Error> {
+        match self {
+            Origin::GitHub => github::GitHubValidator.extract_event_type(headers, payload),
+            Origin::GitLab => {
+                gitlab::GitLabValidator::default().extract_event_type(headers, payload)
+            }
+            Origin::Bitbucket => bitbucket::BitbucketValidator.extract_event_type(headers, payload),
+            Origin::Gitea => gitea::GiteaValidator.extract_event_type(headers, payload),
+            Origin::Webhook => webhook::WebhookValidator.extract_event_type(headers, payload),
+            Origin::StandardWebhooks => {
+                standard::StandardWebhooksValidator::default().extract_event_type(headers, payload)
+            }
+        }
+    }
+
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        match self {
+            Origin::GitHub => github::GitHubValidator.extract_delivery_id(headers),
+            Origin::GitLab => gitlab::GitLabValidator::default().extract_delivery_id(headers),
+            Origin::Bitbucket => bitbucket::BitbucketValidator.extract_delivery_id(headers),
+            Origin::Gitea => gitea::GiteaValidator.extract_delivery_id(headers),
+            Origin::Webhook => webhook::WebhookValidator.extract_delivery_id(headers),
+            Origin::StandardWebhooks => {
+                standard::StandardWebhooksValidator::default().extract_delivery_id(headers)
+            }
         }
     }
 
-    fn extract_event_type(&self, headers: &HeaderMap) -> Result<String, Error> {
+    fn extract_action(&self, payload: &Value) -> Option<String> {
         match self {
-            Origin::GitHub => github::GitHubValidator.extract_event_type(headers),
-            Origin::GitLab => gitlab::GitLabValidator.extract_event_type(headers),
-            Origin::Webhook => webhook::WebhookValidator.extract_event_type(headers),
+            Origin::GitHub => github::GitHubValidator.extract_action(payload),
+            Origin::GitLab => gitlab::GitLabValidator::default().extract_action(payload),
+            Origin::Bitbucket => bitbucket::BitbucketValidator.extract_action(payload),
+            Origin::Gitea => gitea::GiteaValidator.extract_action(payload),
+            Origin::Webhook => webhook::WebhookValidator.extract_action(payload),
+            Origin::StandardWebhooks => {
+                standard::StandardWebhooksValidator::default().extract_action(payload)
+            }
         }
     }
 
@@ -68,11 +149,46 @@ impl WebhookOrigin for Origin {
         headers: &HeaderMap,
         secret: &str,
         body: &[u8],
+    ) -> Result<(), Error> {
+        self.validate_signature_within(headers, secret, body, DEFAULT_TOLERANCE)
+    }
+}
+
+impl Origin {
+    /// Whether deliveries carry an action, so `event.action` filters can match them
+    #[must_use]
+    pub const fn has_actions(&self) -> bool {
+        matches!(self, Origin::GitHub | Origin::GitLab | Origin::Gitea)
+    }
+
+    /// Validates the signature, accepting signed timestamps up to `tolerance` away from the local clock
+    pub fn validate_signature_within(
+        &self,
+        headers: &HeaderMap,
+        secret: &str,
+        body: &[u8],
+        tolerance: Duration,
     ) -> Result<(), Error> {
         match self {
             Origin::GitHub => github::GitHubValidator.validate_signature(headers, secret, body),
-            Origin::GitLab => gitlab::GitLabValidator.validate_signature(headers, secret, body),
+            Origin::GitLab => {
+                gitlab::GitLabValidator { tolerance }.validate_signature(headers, secret, body)
+            }
+            Origin::Bitbucket => {
+                bitbucket::BitbucketValidator.validate_signature(headers, secret, body)
+            }
+            Origin::Gitea => gitea::GiteaValidator.validate_signature(headers, secret, body),
             Origin::Webhook => webhook::WebhookValidator.validate_signature(headers, secret, body),
+            Origin::StandardWebhooks => standard::StandardWebhooksValidator { tolerance }
+                .validate_signature(headers, secret, body),
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+        .collect()
+}
@@ -0,0 +1,213 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::HeaderMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::{Error, WebhookOrigin};
+
+/// Maximum allowed difference between `webhook-timestamp` and the local clock
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_mins(5);
+
+/// Validates deliveries signed following the [Standard Webhooks](https://www.standardwebhooks.com/) spec
+pub struct StandardWebhooksValidator {
+    pub(crate) tolerance: Duration,
+}
+
+impl Default for StandardWebhooksValidator {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+impl WebhookOrigin for StandardWebhooksValidator {
+    fn validate_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
+        const REQUIRED_HEADERS: [&str; 3] =
+            ["webhook-id", "webhook-timestamp", "webhook-signature"];
+
+        for header in REQUIRED_HEADERS {
+            if !headers.contains_key(header) {
+                return Err(Error::MissingHeader(header));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn extract_event_type(&self, _headers: &HeaderMap, payload: &Value) -> Result<String, Error> {
+        // The spec carries the event type in the payload instead of a header
+        payload
+            .get("type")
+            .and_then(Value::as_str)
+            .map(ToString::to_string)
+            .ok_or(Error::UnsupportedEvent)
+    }
+
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("webhook-id")
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    }
+
+    fn validate_signature(
+        &self,
+        headers: &HeaderMap,
+        secret: &str,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        validate_signature(headers, secret, body, self.tolerance)
+    }
+}
+
+/// Checks `webhook-signature` against `HMAC-SHA256(id.timestamp.body)`.
+///
+/// Secrets in `whsec_<base64>` form are decoded, any other secret is used as raw bytes.
+pub(crate) fn validate_signature(
+    headers: &HeaderMap,
+    secret: &str,
+    body: &[u8],
+    tolerance: Duration,
+) -> Result<(), Error> {
+    let get = |header: &'static str| {
+        headers
+            .get(header)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(Error::MissingHeader(header))
+    };
+    let id = get("webhook-id")?;
+    let timestamp = get("webhook-timestamp")?;
+    let signatures = get("webhook-signature")?;
+
+    let sent_at = timestamp
+        .parse::<u64>()
+        .map_err(|_| Error::InvalidTimestamp)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.abs_diff(sent_at) > tolerance.as_secs() {
+        return Err(Error::InvalidTimestamp);
+    }
+
+    let key = secret
+        .strip_prefix("whsec_")
+        .map_or_else(|| Ok(secret.as_bytes().to_vec()), |key| BASE64.decode(key))
+        .map_err(|_| Error::InvalidSignature)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).map_err(|_| Error::InvalidSignature)?;
+    mac.update(id.as_bytes());
+    mac.update(b".");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let expected_signature = BASE64.encode(mac.finalize().into_bytes());
+
+    // Several signatures may be sent at once while the sender rotates keys
+    let matches = signatures
+        .split_whitespace()
+        .filter_map(|signature| signature.strip_prefix("v1,"))
+        .any(|signature| {
+            constant_time_eq::constant_time_eq(signature.as_bytes(), expected_signature.as_bytes())
+        });
+
+    if !matches {
+        return Err(Error::InvalidSignature);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{DEFAULT_TOLERANCE, validate_signature};
+    use crate::{Error, headers};
+
+    // Known-good delivery from the Standard Webhooks specification
+    const SECRET: &str = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+    const ID: &str = "msg_p5jXN8AQM9LWM0D4loKWxJek";
+    const TIMESTAMP: &str = "1614265330";
+    const BODY: &[u8] = br#"{"test": 2432232314}"#;
+    const SIGNATURE: &str = "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=";
+
+    fn validate(signatures: &str, secret: &str, tolerance: Duration) -> Result<(), Error> {
+        let delivery = headers(&[
+            ("webhook-id", ID),
+            ("webhook-timestamp", TIMESTAMP),
+            ("webhook-signature", signatures),
+        ]);
+        validate_signature(&delivery, secret, BODY, tolerance)
+    }
+
+    #[test]
+    fn whsec_secrets_are_decoded() {
+        assert!(validate(SIGNATURE, SECRET, Duration::MAX).is_ok());
+        assert!(matches!(
+            validate(SIGNATURE, "whsec_b3RoZXI=", Duration::MAX),
+            Err(Error::InvalidSignature)
+        ));
+        assert!(matches!(
+            validate(SIGNATURE, "whsec_not base64", Duration::MAX),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn other_secrets_are_raw_keys() {
+        let signature = "v1,3Iu+JbC8cHey9rJURLZfxQoX30vMfLebm9Kn1ePn6f4=";
+        assert!(validate(signature, "plain-secret", Duration::MAX).is_ok());
+    }
+
+    #[test]
+    fn any_of_several_signatures_matches() {
+        let rotated = format!("v1,c2lnbmF0dXJlIG9mIHRoZSBvbGQga2V5 {SIGNATURE}");
+        assert!(validate(&rotated, SECRET, Duration::MAX).is_ok());
+        // Only `v1` signatures are known
+        let other_version = SIGNATURE.replace("v1,", "v2,");
+        assert!(matches!(
+            validate(&other_version, SECRET, Duration::MAX),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn expired_timestamps_are_rejected() {
+        assert!(matches!(
+            validate(SIGNATURE, SECRET, DEFAULT_TOLERANCE),
+            Err(Error::InvalidTimestamp)
+        ));
+    }
+
+    #[test]
+    fn recent_timestamps_are_accepted() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for (offset, valid) in [(0, true), (299, true), (301, false)] {
+            let timestamp = (now - offset).to_string();
+            let mut mac = Hmac::<Sha256>::new_from_slice(b"key").unwrap();
+            mac.update(format!("{ID}.{timestamp}.").as_bytes());
+            mac.update(BODY);
+            let signature = format!("v1,{}", BASE64.encode(mac.finalize().into_bytes()));
+            let delivery = headers(&[
+                ("webhook-id", ID),
+                ("webhook-timestamp", &timestamp),
+                ("webhook-signature", &signature),
+            ]);
+            let result = validate_signature(&delivery, "key", BODY, DEFAULT_TOLERANCE);
+            assert_eq!(result.is_ok(), valid, "{offset}s old");
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use axum::http::HeaderMap;
 use hmac::{Hmac, Mac};
+use serde_json::Value;
 use sha1::Sha1;
 use sha2::Sha256;
 
@@ -20,7 +21,7 @@ impl WebhookOrigin for WebhookValidator {
         Ok(())
     }
 
-    fn extract_event_type(&self, headers: &HeaderMap) -> Result<String, Error> {
+    fn extract_event_type(&self, headers: &HeaderMap, _payload: &Value) -> Result<String, Error> {
         headers
             .get("X-Webhook-Event")
             .and_then(|v| v.to_str().ok())
@@ -28,6 +29,13 @@ impl WebhookOrigin for WebhookValidator {
             .ok_or(Error::MissingHeader("X-Webhook-Event"))
     }
 
+    fn extract_delivery_id(&self, headers: &HeaderMap) -> Option<String> {
+        headers
+            .get("X-Webhook-ID")
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+    }
+
     fn validate_signature(
         &self,
         headers: &HeaderMap,
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use grhooks_config::ConcurrencyPolicy;
+use tokio::sync::{Notify, OwnedMutexGuard};
+
+/// Applies the concurrency policy of every webhook, grouped by concurrency key
+#[derive(Default)]
+pub struct ConcurrencyLimiter {
+    slots: Arc<Slots>,
+}
+
+type Slots = Mutex<HashMap<String, Arc<Slot>>>;
+
+#[derive(Default)]
+struct Slot {
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// Cancellation signal of the newest delivery of the slot
+    newest: Mutex<Option<Arc<Notify>>>,
+}
+
+/// Shared use of a slot, which is removed from the limiter along with its last user
+struct SlotRef {
+    key: String,
+    slot: Arc<Slot>,
+    slots: Arc<Slots>,
+}
+
+impl Deref for SlotRef {
+    type Target = Slot;
+
+    fn deref(&self) -> &Slot {
+        &self.slot
+    }
+}
+
+impl Drop for SlotRef {
+    fn drop(&mut self) {
+        let mut slots = self.slots.lock().unwrap();
+        // Only the limiter references it besides this one, and it can't be reserved meanwhile
+        if Arc::strong_count(&self.slot) == 2 {
+            slots.remove(&self.key);
+        }
+    }
+}
+
+/// Place of a delivery in its slot, obtained without waiting
+pub struct Ticket {
+    slot: SlotRef,
+    turn: Option<Turn>,
+    cancel: Option<Arc<Notify>>,
+}
+
+type Acquire = Pin<Box<dyn Future<Output = OwnedMutexGuard<()>> + Send>>;
+
+/// Turn of a delivery in the lock of its slot
+enum Turn {
+    Held(OwnedMutexGuard<()>),
+    Waiting(Acquire),
+}
+
+impl Turn {
+    /// Joins the queue of the lock right away, which serves waiters in the order they joined
+    fn join(lock: Arc<tokio::sync::Mutex<()>>) -> Self {
+        let mut acquire: Acquire = Box::pin(lock.lock_owned());
+        // Polling registers the waiter, the real waker replaces this one once the ticket is awaited
+        match acquire
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(guard) => Turn::Held(guard),
+            Poll::Pending => Turn::Waiting(acquire),
+        }
+    }
+}
+
+/// Allows a delivery to run its command until it is dropped
+pub struct Permit {
+    slot: SlotRef,
+    _guard: Option<OwnedMutexGuard<()>>,
+    cancel: Option<Arc<Notify>>,
+}
+
+impl ConcurrencyLimiter {
+    /// Reserves a place for a delivery, returns `None` if it must be skipped
+    pub fn reserve(&self, key: String, policy: ConcurrencyPolicy) -> Option<Ticket> {
+        let slot = self
+            .slots
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let mut ticket = Ticket {
+            slot: SlotRef {
+                key,
+                slot,
+                slots: self.slots.clone(),
+            },
+            turn: None,
+            cancel: None,
+        };
+
+        match policy {
+            ConcurrencyPolicy::Parallel => {}
+            ConcurrencyPolicy::Queue => {
+                ticket.turn = Some(Turn::join(ticket.slot.lock.clone()));
+            }
+            ConcurrencyPolicy::SkipIfRunning => {
+                let guard = ticket.slot.lock.clone().try_lock_owned().ok()?;
+                ticket.turn = Some(Turn::Held(guard));
+            }
+            ConcurrencyPolicy::CancelInProgress => {
+                ticket.turn = Some(Turn::join(ticket.slot.lock.clone()));
+                let cancel = Arc::new(Notify::new());
+                let previous = ticket.slot.newest.lock().unwrap().replace(cancel.clone());
+                if let Some(previous) = previous {
+                    previous.notify_one();
+                }
+                ticket.cancel = Some(cancel);
+            }
+        }
+
+        Some(ticket)
+    }
+}
+
+impl Ticket {
+    /// Waits until the delivery is allowed to run
+    pub async fn wait(self) -> Permit {
+        let guard = match self.turn {
+            Some(Turn::Held(guard)) => Some(guard),
+            Some(Turn::Waiting(acquire)) => Some(acquire.await),
+            None => None,
+        };
+
+        Permit {
+            slot: self.slot,
+            _guard: guard,
+            cancel: self.cancel,
+        }
+    }
+}
+
+impl Permit {
+    /// Completes when a newer delivery cancels this one
+    pub async fn cancelled(&self) {
+        match &self.cancel {
+            Some(cancel) => cancel.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(cancel) = &self.cancel else {
+            return;
+        };
+
+        let mut newest = self.slot.newest.lock().unwrap();
+        if newest
+            .as_ref()
+            .is_some_and(|newest| Arc::ptr_eq(newest, cancel))
+        {
+            *newest = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use grhooks_config::ConcurrencyPolicy;
+
+    use super::{ConcurrencyLimiter, Ticket};
+
+    fn slots(limiter: &ConcurrencyLimiter) -> usize {
+        limiter.slots.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn slots_are_removed_once_unused() {
+        let limiter = ConcurrencyLimiter::default();
+        let first = limiter
+            .reserve("a".to_string(), ConcurrencyPolicy::Queue)
+            .unwrap();
+        let second = limiter
+            .reserve("a".to_string(), ConcurrencyPolicy::Queue)
+            .unwrap();
+        let other = limiter
+            .reserve("b".to_string(), ConcurrencyPolicy::Parallel)
+            .unwrap();
+        assert_eq!(slots(&limiter), 2);
+
+        let permit = first.wait().await;
+        drop(other);
+        assert_eq!(slots(&limiter), 1);
+        drop(permit);
+        let permit = second.wait().await;
+        assert_eq!(slots(&limiter), 1);
+        drop(permit);
+        assert_eq!(slots(&limiter), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn queued_deliveries_run_in_reservation_order() {
+        let limiter = ConcurrencyLimiter::default();
+        let reserve = || {
+            limiter
+                .reserve("a".to_string(), ConcurrencyPolicy::Queue)
+                .unwrap()
+        };
+        let running = reserve().wait().await;
+        let first = reserve();
+        let second = reserve();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let run = |ticket: Ticket, index| {
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _permit = ticket.wait().await;
+                order.lock().unwrap().push(index);
+            })
+        };
+        // The second delivery starts waiting before the first one
+        let second = run(second, 2);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let first = run(first, 1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        drop(running);
+        first.await.unwrap();
+        second.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn skipped_deliveries_leave_no_slot() {
+        let limiter = ConcurrencyLimiter::default();
+        let running = limiter
+            .reserve("a".to_string(), ConcurrencyPolicy::SkipIfRunning)
+            .unwrap()
+            .wait()
+            .await;
+        assert!(
+            limiter
+                .reserve("a".to_string(), ConcurrencyPolicy::SkipIfRunning)
+                .is_none()
+        );
+        assert_eq!(slots(&limiter), 1);
+        drop(running);
+        assert_eq!(slots(&limiter), 0);
+    }
+}
@@ -4,6 +4,9 @@ use axum::response::{IntoResponse, Response};
 #[derive(Debug)]
 pub enum HeaderValidationError {
     WebhookNotFound,
+    MissingOrigin,
+    SecretUnavailable,
+    OriginNotAllowed(Vec<grhooks_origin::Origin>),
     OriginValidation(grhooks_origin::Error),
     AxumError(axum::Error),
 }
@@ -14,6 +17,25 @@ impl IntoResponse for HeaderValidationError {
             HeaderValidationError::WebhookNotFound => {
                 (StatusCode::NOT_FOUND, "Webhook not configured".to_string())
             }
+            HeaderValidationError::MissingOrigin => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Webhook origin was not detected".to_string(),
+            ),
+            HeaderValidationError::SecretUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Webhook secret unavailable".to_string(),
+            ),
+            HeaderValidationError::OriginNotAllowed(origins) => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Webhook only accepts deliveries from: {}",
+                    origins
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ),
             HeaderValidationError::AxumError(error) => (StatusCode::BAD_REQUEST, error.to_string()),
             HeaderValidationError::OriginValidation(error) => return error.into_response(),
         };
@@ -1,49 +1,438 @@
+use std::convert::Infallible;
+use std::time::Instant;
+
+use axum::Extension;
+use axum::body::Bytes;
 use axum::extract::{Path, State};
-use axum::http::HeaderMap;
+use axum::http::{HeaderMap, header};
+use axum::response::Response;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{Json, http::StatusCode, response::IntoResponse};
+use futures_util::{StreamExt, stream};
+use grhooks_config::{ExecutionMode, ReplayAction, WebhookConfig};
+use grhooks_core::{ExecuteOptions, OutputLine, OutputStream};
+use grhooks_origin::{Origin, WebhookOrigin};
 use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
 
-use crate::GlobalConfig;
+use crate::AppState;
+use crate::concurrency::{Permit, Ticket};
+use crate::history::Delivery;
+use crate::jobs::JobStatus;
+use crate::redeliver::Redelivery;
 
 pub async fn webhook_handler(
     header: HeaderMap,
-    State(config): State<GlobalConfig>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<AppState>,
     Path(path): Path<String>,
-    Json(value): Json<Value>,
-) -> impl IntoResponse {
-    tracing::debug!("Path: {path:?}");
-    tracing::trace!("Value: {value:?}");
-    let config = config.read().await;
+    redelivery: Option<Extension<Redelivery>>,
+    body: Bytes,
+) -> Response {
+    tracing::debug!("Path: {path:?} ({origin:?})");
+    let Ok(value) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, "Invalid JSON payload".to_string()).into_response();
+    };
+    let config = state.config.read().await;
 
     let Some(webhook) = config.webhooks.iter().find(|w| w.path == path) else {
         return (
             StatusCode::NOT_FOUND,
             format!("Path {path:?} not registered"),
-        );
+        )
+            .into_response();
     };
 
-    let Some(event_type) = header.get("X-GitHub-Event").and_then(|v| v.to_str().ok()) else {
-        return (
-            StatusCode::BAD_REQUEST,
-            "Missing X-GitHub-Event header".to_string(),
-        );
+    let event_type = match origin.extract_event_type(&header, &value) {
+        Ok(event_type) => event_type,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{e:?}")).into_response(),
+    };
+
+    tracing::trace!(
+        "Value: {}",
+        grhooks_core::Redactor::new(webhook, &event_type).redact(&value.to_string())
+    );
+
+    let delivery_id = origin.extract_delivery_id(&header);
+    let id = delivery_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let mut record = state
+        .history
+        .is_some()
+        .then(|| Delivery::received(webhook, origin, &event_type, id.clone(), &header, &body));
+
+    let redelivery = redelivery.map(|Extension(redelivery)| redelivery);
+    let accepted = filter_delivery(webhook, origin, &event_type, &value)
+        // Rendered before the replay check, which would otherwise reject redeliveries of a failed render
+        .and_then(|()| concurrency_key(webhook, &event_type, &value))
+        .and_then(|key| {
+            // Redeliveries reuse the ID of the stored delivery on purpose
+            if redelivery.is_none() {
+                check_replay(&state, webhook, delivery_id.as_deref())?;
+            }
+            Ok(key)
+        });
+    let key = match accepted {
+        Ok(key) => key,
+        Err(dropped) => return dropped.record(&state, record).await,
+    };
+
+    if let Some(record) = &mut record {
+        record.command = grhooks_core::describe_command(webhook, &event_type, &value);
+    }
+
+    // The command runs without holding the config lock, so it can be reloaded meanwhile
+    let run = Run {
+        webhook: webhook.clone(),
+        event_type,
+        value,
+        // Only deliveries that went through the replay check may be forgotten
+        replay_id: delivery_id.filter(|_| redelivery.is_none()),
+        record,
+        options: ExecuteOptions {
+            delivery_id: Some(id),
+            payload: Some(body.as_ref().into()),
+            ..ExecuteOptions::default()
+        },
     };
+    drop(config);
+
+    let Some(ticket) = state
+        .limiter
+        .reserve(format!("{path}\0{key}"), run.webhook.concurrency.policy)
+    else {
+        tracing::info!("Skipping delivery on {path:?}, a command is already running");
+        if let (Some(replay_config), Some(replay_id)) = (&run.webhook.replay, &run.replay_id) {
+            state.replay.forget(&path, replay_id, replay_config);
+        }
+        return Dropped::skipped("Skipped, a command is already running".to_string())
+            .record(&state, run.record)
+            .await;
+    };
+
+    let wait = redelivery.is_some_and(|redelivery| redelivery.wait);
+    if run.webhook.mode == ExecutionMode::Async && !wait {
+        spawn_job(state, run, ticket)
+    } else {
+        let permit = ticket.wait().await;
+        match run_command(&state, run, permit).await {
+            Ok(output) => (StatusCode::OK, output).into_response(),
+            Err(e @ grhooks_core::Error::TimedOut { .. }) => {
+                (StatusCode::GATEWAY_TIMEOUT, e.to_string()).into_response()
+            }
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+/// A delivery that passed every check of its webhook, ready to run the command
+struct Run {
+    webhook: WebhookConfig,
+    event_type: String,
+    value: Value,
+    /// Delivery ID recorded by the replay check, forgotten if the command fails
+    replay_id: Option<String>,
+    record: Option<Delivery>,
+    options: ExecuteOptions,
+}
+
+/// Why a delivery was dropped before reaching its command
+struct Dropped {
+    status: JobStatus,
+    code: StatusCode,
+    message: String,
+}
 
+impl Dropped {
+    fn skipped(message: String) -> Self {
+        Self {
+            status: JobStatus::Skipped,
+            code: StatusCode::OK,
+            message,
+        }
+    }
+
+    fn rejected(code: StatusCode, message: String) -> Self {
+        Self {
+            status: JobStatus::Rejected,
+            code,
+            message,
+        }
+    }
+
+    fn failed(error: &impl std::fmt::Display) -> Self {
+        Self {
+            status: JobStatus::Failed,
+            code: StatusCode::INTERNAL_SERVER_ERROR,
+            message: error.to_string(),
+        }
+    }
+
+    /// Answers the delivery, recording why it was dropped
+    async fn record(self, state: &AppState, record: Option<Delivery>) -> Response {
+        if let (Some(history), Some(mut record)) = (&state.history, record) {
+            record.status = self.status;
+            record.output.clone_from(&self.message);
+            history.record(record).await;
+        }
+        (self.code, self.message).into_response()
+    }
+}
+
+/// Checks the delivery against the `events`, `if` and `paths` filters of the webhook
+fn filter_delivery(
+    webhook: &WebhookConfig,
+    origin: Origin,
+    event_type: &str,
+    value: &Value,
+) -> Result<(), Dropped> {
+    let path = &webhook.path;
     if !webhook.events.is_empty()
         && !webhook.events.contains("*")
         && !webhook.events.contains(event_type)
     {
-        return (
-            StatusCode::BAD_REQUEST,
-            format!("Event '{event_type}' not allowed"),
-        );
+        // Entries like `pull_request.opened` only accept some actions of the event
+        let prefix = format!("{event_type}.");
+        let actions = webhook
+            .events
+            .iter()
+            .filter_map(|event| event.strip_prefix(&prefix))
+            .collect::<Vec<_>>();
+        if actions.is_empty() {
+            return Err(Dropped::rejected(
+                StatusCode::BAD_REQUEST,
+                format!("Event '{event_type}' not allowed"),
+            ));
+        }
+
+        if !origin.has_actions() {
+            tracing::warn!(
+                "Skipping delivery on {path:?}, {origin} deliveries have no action to match {actions:?}"
+            );
+            return Err(Dropped::skipped(format!(
+                "Skipped, {origin} origin has no action to match '{event_type}.<action>'"
+            )));
+        }
+
+        let action = origin.extract_action(value);
+        if !action
+            .as_deref()
+            .is_some_and(|action| actions.contains(&action))
+        {
+            let action = action.unwrap_or_default();
+            tracing::info!("Skipping delivery on {path:?}, action {action:?} not allowed");
+            return Err(Dropped::skipped(format!(
+                "Skipped, action '{event_type}.{action}' not allowed"
+            )));
+        }
+    }
+
+    if let Some(condition) = &webhook.condition {
+        match grhooks_core::evaluate_condition(condition, event_type, value) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!("Skipping delivery on {path:?}, condition {condition:?} is false");
+                return Err(Dropped::skipped("Skipped, condition not met".to_string()));
+            }
+            Err(e) => {
+                tracing::error!("Error evaluating condition of {path:?}: {e}");
+                return Err(Dropped::failed(&e));
+            }
+        }
     }
 
-    match grhooks_core::execute_command(webhook, event_type, &value).await {
-        Ok(output) => (StatusCode::OK, output),
+    match grhooks_core::matches_paths(webhook, value) {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            tracing::info!("Skipping delivery on {path:?}, no changed file matches its paths");
+            Err(Dropped::skipped(
+                "Skipped, no matching changed files".to_string(),
+            ))
+        }
         Err(e) => {
-            tracing::error!("Error executing command: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            tracing::error!("Error matching changed files of {path:?}: {e}");
+            Err(Dropped::failed(&e))
+        }
+    }
+}
+
+fn concurrency_key(
+    webhook: &WebhookConfig,
+    event_type: &str,
+    value: &Value,
+) -> Result<String, Dropped> {
+    match &webhook.concurrency.key {
+        Some(key) => {
+            grhooks_core::render_template(key, event_type, value).map_err(|e| Dropped::failed(&e))
         }
+        None => Ok(String::new()),
     }
 }
+
+/// Records the delivery ID, dropping deliveries already processed
+fn check_replay(
+    state: &AppState,
+    webhook: &WebhookConfig,
+    delivery_id: Option<&str>,
+) -> Result<(), Dropped> {
+    let Some(replay_config) = &webhook.replay else {
+        return Ok(());
+    };
+    let Some(delivery_id) = delivery_id else {
+        return Err(Dropped::rejected(
+            StatusCode::BAD_REQUEST,
+            "Missing delivery ID header".to_string(),
+        ));
+    };
+
+    if state
+        .replay
+        .check(&webhook.path, delivery_id, replay_config)
+    {
+        return Ok(());
+    }
+
+    tracing::warn!("Replayed delivery {delivery_id} on {:?}", webhook.path);
+    Err(match replay_config.action {
+        ReplayAction::Reject => Dropped::rejected(
+            StatusCode::CONFLICT,
+            format!("Delivery {delivery_id} already processed"),
+        ),
+        ReplayAction::Ignore => {
+            Dropped::skipped(format!("Delivery {delivery_id} already processed, ignored"))
+        }
+    })
+}
+
+/// Runs the command in the background, answering with the job to follow it
+fn spawn_job(state: AppState, mut run: Run, ticket: Ticket) -> Response {
+    let job = state.jobs.create(&run.webhook.path, &run.event_type);
+    let location = format!("/_grhooks/jobs/{}", job.id);
+    tracing::info!("Queued job {} for {:?}", job.id, run.webhook.path);
+
+    let job_id = job.id.clone();
+    tokio::spawn(async move {
+        let (output, mut lines) = tokio::sync::mpsc::unbounded_channel();
+        let forward = tokio::spawn({
+            let jobs = state.jobs.clone();
+            let job_id = job_id.clone();
+            async move {
+                while let Some(line) = lines.recv().await {
+                    jobs.push_line(&job_id, line);
+                }
+            }
+        });
+
+        let permit = ticket.wait().await;
+        state.jobs.start(&job_id);
+        run.options.output = Some(output);
+        let result = run_command(&state, run, permit).await;
+        // Every line reaches the subscribers before their stream ends
+        _ = forward.await;
+        state.jobs.finish(&job_id, &result);
+    });
+
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(job),
+    )
+        .into_response()
+}
+
+async fn run_command(
+    state: &AppState,
+    run: Run,
+    permit: Permit,
+) -> Result<String, grhooks_core::Error> {
+    let Run {
+        webhook,
+        event_type,
+        value,
+        replay_id,
+        record,
+        options,
+    } = run;
+
+    let started = Instant::now();
+    let result = tokio::select! {
+        result = grhooks_core::execute_command(&webhook, &event_type, &value, &options) => result,
+        () = permit.cancelled() => Err(grhooks_core::Error::Cancelled),
+    };
+    drop(permit);
+
+    if let Err(e) = &result {
+        tracing::error!("Error executing command: {e}");
+        // Failed deliveries may be redelivered with the same ID
+        if let (Some(replay_config), Some(replay_id)) = (&webhook.replay, &replay_id) {
+            state.replay.forget(&webhook.path, replay_id, replay_config);
+        }
+    }
+
+    if let (Some(history), Some(mut record)) = (&state.history, record) {
+        record.status = JobStatus::from_result(&result);
+        record.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        match &result {
+            Ok(output) => {
+                record.exit_code = Some(0);
+                record.output.clone_from(output);
+            }
+            Err(e) => {
+                record.exit_code = e.exit_code();
+                record.output = e.to_string();
+            }
+        }
+        history.record(record).await;
+    }
+
+    result
+}
+
+pub async fn job_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.jobs.get(&id) {
+        Some(job) => Json(job).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Job {id:?} not found")).into_response(),
+    }
+}
+
+pub async fn job_stream_handler(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some(subscription) = state.jobs.subscribe(&id) else {
+        return (StatusCode::NOT_FOUND, format!("Job {id:?} not found")).into_response();
+    };
+
+    let backlog = stream::iter(subscription.lines).map(|line| line_event(&line));
+    let live = stream::unfold(subscription.receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(line) => return Some((line_event(&line), Some(receiver))),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Output stream lagged behind, skipped {skipped} lines");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let end = stream::once(async move {
+        let event = Event::default().event("end");
+        match state.jobs.get(&id) {
+            Some(job) => event.json_data(job).unwrap_or_default(),
+            None => event,
+        }
+    });
+
+    Sse::new(backlog.chain(live).chain(end).map(Ok::<_, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn line_event(output: &OutputLine) -> Event {
+    let name = match output.stream {
+        OutputStream::Stdout => "stdout",
+        OutputStream::Stderr => "stderr",
+    };
+    // Carriage returns redraw the line, only its last state is sent
+    let line = output.line.rsplit('\r').next().unwrap_or_default();
+    Event::default().event(name).data(line)
+}
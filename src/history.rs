@@ -0,0 +1,346 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use axum::http::HeaderMap;
+use grhooks_config::{HistoryConfig, WebhookConfig};
+use grhooks_origin::Origin;
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{JobStatus, now};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A delivery as received, along with what its command did
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Delivery {
+    pub id: String,
+    pub received_at: u64,
+    pub path: String,
+    pub origin: Origin,
+    pub event_type: String,
+    /// Headers as received, with secret values redacted
+    pub headers: Vec<(String, String)>,
+    /// Raw body, exactly as it was validated, left empty for deliveries with an invalid signature
+    #[serde(with = "base64_body")]
+    pub body: Vec<u8>,
+    /// Size of the received body in bytes
+    #[serde(default)]
+    pub body_size: usize,
+    pub command: Option<String>,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub output: String,
+}
+
+impl Delivery {
+    /// A delivery as received, before it went through the filters of its webhook
+    pub fn received(
+        webhook: &WebhookConfig,
+        origin: Origin,
+        event_type: &str,
+        id: String,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Self {
+        let redactor = grhooks_core::Redactor::new(webhook, event_type);
+        Self {
+            id,
+            received_at: now(),
+            path: webhook.path.clone(),
+            origin,
+            event_type: event_type.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = String::from_utf8_lossy(value.as_bytes());
+                    (name.to_string(), redactor.redact(&value).into_owned())
+                })
+                .collect(),
+            body: body.to_vec(),
+            body_size: body.len(),
+            command: None,
+            status: JobStatus::Queued,
+            exit_code: None,
+            duration_ms: 0,
+            output: String::new(),
+        }
+    }
+}
+
+/// Bodies are stored in base64, signatures cover bytes that are not always valid UTF-8
+mod base64_body {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Append-only JSON Lines log of the processed deliveries
+pub struct HistoryStore {
+    config: HistoryConfig,
+    state: Mutex<HistoryState>,
+}
+
+#[derive(Default)]
+struct HistoryState {
+    entries: usize,
+    oldest: Option<u64>,
+}
+
+impl HistoryStore {
+    pub fn new(config: HistoryConfig) -> Self {
+        let store = Self {
+            config,
+            state: Mutex::default(),
+        };
+        let deliveries = store.read();
+        *store.state.lock().unwrap() = HistoryState {
+            entries: deliveries.len(),
+            oldest: deliveries.first().map(|delivery| delivery.received_at),
+        };
+        store
+    }
+
+    /// Appends the delivery to the log, without blocking the runtime on the file
+    pub async fn record(self: &Arc<Self>, delivery: Delivery) {
+        let store = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || store.append(&delivery)).await {
+            tracing::error!("Cannot record delivery: {e}");
+        }
+    }
+
+    /// Finds the latest delivery stored with the given ID
+    pub async fn find(self: &Arc<Self>, id: &str) -> Option<Delivery> {
+        let store = self.clone();
+        let id = id.to_string();
+        tokio::task::spawn_blocking(move || {
+            store
+                .read()
+                .into_iter()
+                .rev()
+                .find(|delivery| delivery.id == id)
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    fn append(&self, delivery: &Delivery) {
+        let mut state = self.state.lock().unwrap();
+
+        let line = match serde_json::to_string(delivery) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Cannot serialize delivery {}: {e}", delivery.id);
+                return;
+            }
+        };
+        let appended = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(e) = appended {
+            tracing::error!("Cannot record delivery in {:?}: {e}", self.config.path);
+            return;
+        }
+
+        state.entries += 1;
+        state.oldest.get_or_insert(delivery.received_at);
+
+        // Rewriting the whole file is expensive, so it's only compacted
+        // once it grew 10% over the limit or holds expired deliveries
+        let slack = self.config.max_entries / 10 + 1;
+        let expired = self
+            .expiration()
+            .is_some_and(|expiration| state.oldest.is_some_and(|oldest| oldest < expiration));
+        if state.entries > self.config.max_entries + slack || expired {
+            *state = self.compact();
+        }
+    }
+
+    /// Reads every stored delivery, oldest first
+    fn read(&self) -> Vec<Delivery> {
+        let content = match std::fs::read_to_string(&self.config.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                tracing::error!("Cannot read deliveries from {:?}: {e}", self.config.path);
+                return Vec::new();
+            }
+        };
+
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| {
+                serde_json::from_str(line)
+                    .inspect_err(|e| tracing::warn!("Skipping invalid delivery record: {e}"))
+                    .ok()
+            })
+            .collect()
+    }
+
+    fn expiration(&self) -> Option<u64> {
+        self.config
+            .max_age_days
+            .map(|days| now().saturating_sub(days * SECONDS_PER_DAY))
+    }
+
+    fn compact(&self) -> HistoryState {
+        let mut deliveries = self.read();
+        if let Some(expiration) = self.expiration() {
+            deliveries.retain(|delivery| delivery.received_at >= expiration);
+        }
+        let skip = deliveries.len().saturating_sub(self.config.max_entries);
+        let deliveries = &deliveries[skip..];
+
+        let mut content = String::new();
+        for delivery in deliveries {
+            if let Ok(line) = serde_json::to_string(delivery) {
+                content.push_str(&line);
+                content.push('\n');
+            }
+        }
+
+        // Written next to the log and renamed, so a crash never truncates it
+        let temp_path = self.config.path.with_extension("tmp");
+        let written = std::fs::write(&temp_path, content)
+            .and_then(|()| std::fs::rename(&temp_path, &self.config.path));
+        if let Err(e) = written {
+            tracing::error!("Cannot compact deliveries in {:?}: {e}", self.config.path);
+        }
+
+        HistoryState {
+            entries: deliveries.len(),
+            oldest: deliveries.first().map(|delivery| delivery.received_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use grhooks_config::HistoryConfig;
+    use grhooks_origin::Origin;
+
+    use super::{Delivery, HistoryStore, SECONDS_PER_DAY};
+    use crate::jobs::{JobStatus, now};
+
+    fn delivery(id: &str, received_at: u64) -> Delivery {
+        Delivery {
+            id: id.to_string(),
+            received_at,
+            path: "deploy".to_string(),
+            origin: Origin::GitHub,
+            event_type: "push".to_string(),
+            headers: Vec::new(),
+            body: b"{}".to_vec(),
+            body_size: 2,
+            command: None,
+            status: JobStatus::Succeeded,
+            exit_code: Some(0),
+            duration_ms: 0,
+            output: String::new(),
+        }
+    }
+
+    fn store(
+        name: &str,
+        max_entries: usize,
+        max_age_days: Option<u64>,
+    ) -> (Arc<HistoryStore>, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "grhooks-history-{name}-{}.jsonl",
+            std::process::id()
+        ));
+        _ = std::fs::remove_file(&path);
+        let config = HistoryConfig {
+            path: path.clone(),
+            max_entries,
+            max_age_days,
+        };
+        (Arc::new(HistoryStore::new(config)), path)
+    }
+
+    #[tokio::test]
+    async fn bodies_are_stored_as_raw_bytes() {
+        let (history, path) = store("raw", 10, None);
+        let mut record = delivery("binary", now());
+        record.body = vec![0xff, 0xfe, b'{', 0x00];
+        history.record(record).await;
+
+        let found = history.find("binary").await.unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(found.body, [0xff, 0xfe, b'{', 0x00]);
+        assert!(history.find("other").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn entries_over_the_limit_are_compacted() {
+        let (history, path) = store("limit", 10, None);
+        // Compaction waits for 10% more deliveries than the limit
+        for index in 0..13 {
+            history.record(delivery(&index.to_string(), now())).await;
+        }
+
+        let ids = history
+            .read()
+            .into_iter()
+            .map(|delivery| delivery.id)
+            .collect::<Vec<_>>();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ids, ["3", "4", "5", "6", "7", "8", "9", "10", "11", "12"]);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_dropped() {
+        let (history, path) = store("expired", 10, Some(1));
+        history
+            .record(delivery("old", now() - 2 * SECONDS_PER_DAY))
+            .await;
+        history.record(delivery("new", now())).await;
+
+        let ids = history
+            .read()
+            .into_iter()
+            .map(|delivery| delivery.id)
+            .collect::<Vec<_>>();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ids, ["new"]);
+    }
+
+    #[tokio::test]
+    async fn counts_are_restored_from_the_file() {
+        let (history, path) = store("reload", 2, None);
+        for index in 0..3 {
+            history.record(delivery(&index.to_string(), now())).await;
+        }
+
+        let config = HistoryConfig {
+            path: path.clone(),
+            max_entries: 2,
+            max_age_days: None,
+        };
+        let reloaded = Arc::new(HistoryStore::new(config));
+        reloaded.record(delivery("3", now())).await;
+
+        let ids = reloaded
+            .read()
+            .into_iter()
+            .map(|delivery| delivery.id)
+            .collect::<Vec<_>>();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ids, ["2", "3"]);
+    }
+}
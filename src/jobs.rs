@@ -0,0 +1,186 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use grhooks_core::OutputLine;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Number of jobs kept in memory to be queried once finished
+const MAX_JOBS: usize = 1024;
+/// Number of output lines of a running job sent to new subscribers
+const MAX_LINES: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    #[serde(rename = "timed_out")]
+    TimedOut,
+    /// Dropped by the `skip-if-running` concurrency policy or filtered out by the webhook
+    Skipped,
+    /// Refused before running, like deliveries with an invalid signature
+    Rejected,
+}
+
+impl JobStatus {
+    pub fn from_result(result: &Result<String, grhooks_core::Error>) -> Self {
+        match result {
+            Ok(_) => JobStatus::Succeeded,
+            Err(grhooks_core::Error::Cancelled) => JobStatus::Cancelled,
+            Err(grhooks_core::Error::TimedOut { .. }) => JobStatus::TimedOut,
+            Err(_) => JobStatus::Failed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub path: String,
+    pub event_type: String,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub output: Option<String>,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+/// Keeps track of the commands running in the background
+#[derive(Default)]
+pub struct JobStore {
+    inner: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    jobs: HashMap<String, Job>,
+    order: VecDeque<String>,
+    /// Output of the jobs that did not finish yet
+    live: HashMap<String, LiveOutput>,
+}
+
+struct LiveOutput {
+    lines: VecDeque<OutputLine>,
+    sender: broadcast::Sender<OutputLine>,
+}
+
+/// Output of a job for a new subscriber: the latest lines, then the live ones
+pub struct Subscription {
+    pub lines: Vec<OutputLine>,
+    /// `None` once the job finished
+    pub receiver: Option<broadcast::Receiver<OutputLine>>,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl JobStore {
+    pub fn create(&self, path: &str, event_type: &str) -> Job {
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            path: path.to_string(),
+            event_type: event_type.to_string(),
+            status: JobStatus::Queued,
+            exit_code: None,
+            output: None,
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.jobs.insert(job.id.clone(), job.clone());
+        inner.order.push_back(job.id.clone());
+        inner.live.insert(
+            job.id.clone(),
+            LiveOutput {
+                lines: VecDeque::new(),
+                sender: broadcast::channel(MAX_LINES).0,
+            },
+        );
+        while inner.order.len() > MAX_JOBS {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.jobs.remove(&oldest);
+                inner.live.remove(&oldest);
+            }
+        }
+
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.inner.lock().unwrap().jobs.get(id).cloned()
+    }
+
+    pub fn subscribe(&self, id: &str) -> Option<Subscription> {
+        let inner = self.inner.lock().unwrap();
+        if !inner.jobs.contains_key(id) {
+            return None;
+        }
+
+        // Both are taken under the lock, so no line is missed or sent twice
+        Some(match inner.live.get(id) {
+            Some(live) => Subscription {
+                lines: live.lines.iter().cloned().collect(),
+                receiver: Some(live.sender.subscribe()),
+            },
+            None => Subscription {
+                lines: Vec::new(),
+                receiver: None,
+            },
+        })
+    }
+
+    pub fn push_line(&self, id: &str, line: OutputLine) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(output) = inner.live.get_mut(id) else {
+            return;
+        };
+
+        if output.lines.len() == MAX_LINES {
+            output.lines.pop_front();
+        }
+        output.lines.push_back(line.clone());
+        // Nobody may be following the job
+        _ = output.sender.send(line);
+    }
+
+    pub fn start(&self, id: &str) {
+        if let Some(job) = self.inner.lock().unwrap().jobs.get_mut(id) {
+            job.status = JobStatus::Running;
+            job.started_at = Some(now());
+        }
+    }
+
+    pub fn finish(&self, id: &str, result: &Result<String, grhooks_core::Error>) {
+        let mut inner = self.inner.lock().unwrap();
+        // Dropping the sender ends the streams of the subscribers
+        inner.live.remove(id);
+        let Some(job) = inner.jobs.get_mut(id) else {
+            return;
+        };
+
+        job.finished_at = Some(now());
+        job.status = JobStatus::from_result(result);
+        match result {
+            Ok(output) => {
+                job.exit_code = Some(0);
+                job.output = Some(output.clone());
+            }
+            Err(e) => {
+                job.exit_code = e.exit_code();
+                job.output = Some(e.to_string());
+            }
+        }
+    }
+}
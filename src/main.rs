@@ -1,67 +1,162 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use axum::{Router, routing::post};
-use grhooks_config::Config;
+use axum::extract::FromRef;
+use axum::{
+    Router,
+    routing::{get, post},
+};
+use grhooks_config::{CliCommand, Config};
 use notify::event::{DataChange, ModifyKind};
 use notify::{EventHandler, EventKind, Watcher};
 use tokio::sync::RwLock;
 use tracing::level_filters::LevelFilter;
 
+mod concurrency;
 mod errors;
 mod handlers;
+mod history;
+mod jobs;
+mod redeliver;
+mod replay;
 mod validator;
 
 pub(crate) type GlobalConfig = Arc<RwLock<Config>>;
 
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub config: GlobalConfig,
+    pub replay: Arc<replay::ReplayGuard>,
+    pub jobs: Arc<jobs::JobStore>,
+    pub limiter: Arc<concurrency::ConcurrencyLimiter>,
+    pub history: Option<Arc<history::HistoryStore>>,
+}
+
+impl FromRef<AppState> for GlobalConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let (config_path, config) = grhooks_config::get_config();
+    let (config_path, config, command) = grhooks_config::get_config();
     tracing_subscriber::fmt()
         .with_max_level(LevelFilter::from_str(&config.verbose).unwrap_or(LevelFilter::INFO))
         .with_file(true)
         .with_line_number(true)
         .init();
-    config.print_paths();
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", config.port))
+    match command {
+        CliCommand::Serve => {
+            config.print_paths();
+            serve(config_path, config).await;
+        }
+        CliCommand::Replay {
+            delivery_id,
+            skip_signature,
+        } => replay(&config, &delivery_id, skip_signature).await,
+    }
+}
+
+pub(crate) fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/{*path}", post(handlers::webhook_handler))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            validator::validate_signature_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            validator::validate_headers,
+        ))
+        .route("/_grhooks/jobs/{id}", get(handlers::job_handler))
+        .route(
+            "/_grhooks/jobs/{id}/stream",
+            get(handlers::job_stream_handler),
+        )
+        .route(
+            "/_grhooks/deliveries/{id}/replay",
+            post(redeliver::replay_handler),
+        )
+        .with_state(state)
+}
+
+async fn serve(config_path: PathBuf, config: Config) {
+    let port = config.port;
+    let history = config
+        .history
+        .clone()
+        .map(|history| Arc::new(history::HistoryStore::new(history)));
+    let state = AppState {
+        config: Arc::new(RwLock::new(config)),
+        replay: Arc::default(),
+        jobs: Arc::default(),
+        limiter: Arc::default(),
+        history,
+    };
+
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
         .await
         .unwrap();
-    let state = Arc::new(RwLock::new(config));
 
-    let mut manifest_watcher = notify::recommended_watcher(listen_config_changes(state.clone()))
-        .expect("Cannot create watcher for manifest");
+    let mut manifest_watcher = notify::recommended_watcher(listen_config_changes(
+        state.config.clone(),
+        config_path.clone(),
+    ))
+    .expect("Cannot create watcher for manifest");
     manifest_watcher
         .watch(config_path.as_path(), notify::RecursiveMode::Recursive)
         .unwrap();
 
-    let app = Router::new()
-        .route("/{*path}", post(handlers::webhook_handler))
-        .layer(axum::middleware::from_fn(validator::validate_headers))
-        .layer(axum::middleware::from_fn_with_state(
-            state.clone(),
-            validator::validate_signature_middleware,
-        ))
-        .with_state(state);
+    let app = app(state);
 
     println!("listening on {}", listener.local_addr().unwrap());
 
     axum::serve(listener, app).await.unwrap();
 }
 
-fn listen_config_changes(state: GlobalConfig) -> impl EventHandler {
+async fn replay(config: &Config, delivery_id: &str, skip_signature: bool) {
+    match redeliver::request_replay(config, delivery_id, skip_signature).await {
+        Ok((status, body)) => {
+            println!("{status}\n{body}");
+            if !status.is_success() {
+                std::process::exit(1);
+            }
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn listen_config_changes(state: GlobalConfig, config_path: PathBuf) -> impl EventHandler {
     move |res: notify::Result<notify::Event>| {
         let Ok(event) = res else {
             tracing::error!("Error watching config file: {}", res.unwrap_err());
             return;
         };
         if event.kind == EventKind::Modify(ModifyKind::Data(DataChange::Any)) {
-            let mut config = state.blocking_write();
-            config.webhooks = Vec::new(); // restore webhooks
-            event.paths.iter().for_each(|path| {
+            for path in &event.paths {
                 tracing::info!("Config file changed: {path:?}");
-                config.merge(grhooks_config::parse_config(path));
-            });
+            }
+            // Read from the top, a directory spreads the config over several files
+            let reloaded = match grhooks_config::parse_config(&config_path) {
+                Ok(reloaded) => reloaded,
+                Err(errors) => {
+                    tracing::error!(
+                        "Keeping the current configuration, the new one is invalid:\n{errors}"
+                    );
+                    return;
+                }
+            };
+            let mut config = state.blocking_write();
+            config.webhooks = reloaded.webhooks;
+            config.timeout = reloaded.timeout;
+            // A rotated token applies right away, like webhook secrets
+            config.admin_token = reloaded.admin_token;
             config.print_paths();
         }
     }
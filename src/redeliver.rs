@@ -0,0 +1,129 @@
+use axum::body::Body;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use grhooks_config::Config;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tower::ServiceExt;
+
+use crate::AppState;
+use crate::history::Delivery;
+
+/// Marks requests built from stored deliveries, clients cannot set request extensions
+#[derive(Clone, Copy, Debug)]
+pub struct Redelivery {
+    pub skip_signature: bool,
+    /// Runs `async` webhooks inline, so the caller gets the command result
+    pub wait: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayParams {
+    #[serde(default)]
+    skip_signature: bool,
+    #[serde(default)]
+    wait: bool,
+}
+
+/// Sends a stored delivery through the same validation and execution pipeline as live ones
+pub async fn redeliver(state: AppState, delivery: &Delivery, redelivery: Redelivery) -> Response {
+    let mut request = Request::post(format!("/{}", delivery.path))
+        .body(Body::from(delivery.body.clone()))
+        .unwrap_or_default();
+
+    let headers = request.headers_mut();
+    for (name, value) in &delivery.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    request.extensions_mut().insert(redelivery);
+
+    tracing::info!("Redelivering {} to {:?}", delivery.id, delivery.path);
+    match crate::app(state).oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+pub async fn replay_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<ReplayParams>,
+) -> Response {
+    let config = state.config.read().await;
+    let Some(admin_token) = &config.admin_token else {
+        return (StatusCode::NOT_FOUND, "Admin API disabled".to_string()).into_response();
+    };
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq::constant_time_eq(token.as_bytes(), admin_token.expose().as_bytes()) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token".to_string()).into_response();
+    }
+    drop(config);
+
+    let delivery = match &state.history {
+        Some(history) => history.find(&id).await,
+        None => None,
+    };
+    let Some(delivery) = delivery else {
+        return (StatusCode::NOT_FOUND, format!("Delivery {id:?} not found")).into_response();
+    };
+
+    let redelivery = Redelivery {
+        skip_signature: params.skip_signature,
+        wait: params.wait,
+    };
+    redeliver(state, &delivery, redelivery).await
+}
+
+/// Asks the running daemon to redeliver a stored delivery through the admin API, so it goes
+/// through the daemon's concurrency policies like any other delivery
+pub async fn request_replay(
+    config: &Config,
+    delivery_id: &str,
+    skip_signature: bool,
+) -> Result<(StatusCode, String), String> {
+    let Some(admin_token) = &config.admin_token else {
+        return Err("Replaying requires an `admin_token` in the configuration".to_string());
+    };
+
+    let stream = TcpStream::connect(("127.0.0.1", config.port))
+        .await
+        .map_err(|e| format!("Cannot reach grhooks on port {}: {e}", config.port))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::spawn(connection);
+
+    let request = Request::post(format!(
+        "/_grhooks/deliveries/{delivery_id}/replay?wait=true&skip_signature={skip_signature}"
+    ))
+    .header(header::HOST, format!("127.0.0.1:{}", config.port))
+    .header(
+        header::AUTHORIZATION,
+        format!("Bearer {}", admin_token.expose()),
+    )
+    .body(Body::empty())
+    .map_err(|e| format!("Invalid delivery ID {delivery_id:?}: {e}"))?;
+
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+
+use grhooks_config::ReplayConfig;
+
+/// Remembers recently seen delivery IDs for every webhook path
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<String, SeenDeliveries>>,
+    /// Writes to the persistence files, applied in order by a background thread
+    writes: Sender<Persist>,
+}
+
+enum Persist {
+    Append {
+        path: PathBuf,
+        id: String,
+    },
+    Rewrite {
+        path: PathBuf,
+        content: String,
+    },
+    /// Answers once every previous write was applied
+    #[cfg(test)]
+    Flush(Sender<()>),
+}
+
+#[derive(Default)]
+struct SeenDeliveries {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+    /// Lines appended to the persistence file since it was last rewritten
+    appended: usize,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        let (writes, receiver) = std::sync::mpsc::channel::<Persist>();
+        // Handlers never wait on the disk, the thread ends along with the guard
+        std::thread::spawn(move || {
+            for write in receiver {
+                write.apply();
+            }
+        });
+
+        Self {
+            seen: Mutex::default(),
+            writes,
+        }
+    }
+}
+
+impl ReplayGuard {
+    /// Records the delivery, returns `false` if it was already seen for this path
+    pub fn check(&self, path: &str, id: &str, config: &ReplayConfig) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let deliveries = seen
+            .entry(path.to_string())
+            .or_insert_with(|| SeenDeliveries::load(config));
+
+        if deliveries.ids.contains(id) {
+            return false;
+        }
+
+        let write = deliveries.insert(id, config);
+        self.write(write);
+        true
+    }
+
+    /// Forgets a delivery so it can be sent again, e.g. after its command failed
+    pub fn forget(&self, path: &str, id: &str, config: &ReplayConfig) {
+        let mut seen = self.seen.lock().unwrap();
+        let Some(deliveries) = seen.get_mut(path) else {
+            return;
+        };
+
+        if deliveries.ids.remove(id) {
+            deliveries.order.retain(|seen| seen != id);
+            let write = deliveries.persist(config);
+            self.write(write);
+        }
+    }
+
+    /// Queued while the lock of the IDs is held, so the file sees the changes in their order
+    fn write(&self, write: Option<Persist>) {
+        if let Some(write) = write {
+            _ = self.writes.send(write);
+        }
+    }
+
+    #[cfg(test)]
+    fn flush(&self) {
+        let (done, flushed) = std::sync::mpsc::channel();
+        self.write(Some(Persist::Flush(done)));
+        _ = flushed.recv();
+    }
+}
+
+impl Persist {
+    fn apply(self) {
+        match self {
+            Persist::Append { path, id } => {
+                let appended = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| writeln!(file, "{id}"));
+                if let Err(e) = appended {
+                    tracing::error!("Cannot store delivery ID in {path:?}: {e}");
+                }
+            }
+            Persist::Rewrite { path, content } => {
+                if let Err(e) = std::fs::write(&path, content) {
+                    tracing::error!("Cannot store delivery IDs in {path:?}: {e}");
+                }
+            }
+            #[cfg(test)]
+            Persist::Flush(done) => _ = done.send(()),
+        }
+    }
+}
+
+impl SeenDeliveries {
+    fn load(config: &ReplayConfig) -> Self {
+        let mut deliveries = Self::default();
+        let Some(path) = &config.persist else {
+            return deliveries;
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                for id in content.lines().filter(|line| !line.is_empty()) {
+                    deliveries.remember(id, config.capacity);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::error!("Cannot read delivery IDs from {path:?}: {e}"),
+        }
+
+        deliveries
+    }
+
+    fn remember(&mut self, id: &str, capacity: usize) {
+        if self.ids.insert(id.to_string()) {
+            self.order.push_back(id.to_string());
+        }
+
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+
+    fn insert(&mut self, id: &str, config: &ReplayConfig) -> Option<Persist> {
+        self.remember(id, config.capacity);
+
+        let path = config.persist.clone()?;
+
+        // Appending is cheap, the file is only rewritten once it holds
+        // twice as many IDs as the cache
+        self.appended += 1;
+        if self.appended > config.capacity {
+            return self.persist(config);
+        }
+
+        Some(Persist::Append {
+            path,
+            id: id.to_string(),
+        })
+    }
+
+    fn persist(&mut self, config: &ReplayConfig) -> Option<Persist> {
+        let path = config.persist.clone()?;
+
+        let mut content = self
+            .order
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n");
+        content.push('\n');
+
+        self.appended = 0;
+        Some(Persist::Rewrite { path, content })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use grhooks_config::{ReplayAction, ReplayConfig};
+
+    use super::ReplayGuard;
+
+    fn config(capacity: usize, persist: Option<PathBuf>) -> ReplayConfig {
+        ReplayConfig {
+            action: ReplayAction::Reject,
+            capacity,
+            persist,
+        }
+    }
+
+    fn persist_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("grhooks-replay-{name}-{}", std::process::id()));
+        _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn duplicates_are_rejected_per_path() {
+        let guard = ReplayGuard::default();
+        let config = config(10, None);
+        assert!(guard.check("deploy", "a", &config));
+        assert!(!guard.check("deploy", "a", &config));
+        assert!(guard.check("other", "a", &config));
+    }
+
+    #[test]
+    fn oldest_ids_are_dropped_past_the_capacity() {
+        let guard = ReplayGuard::default();
+        let config = config(2, None);
+        for id in ["a", "b", "c"] {
+            assert!(guard.check("deploy", id, &config));
+        }
+        assert!(!guard.check("deploy", "c", &config));
+        assert!(guard.check("deploy", "a", &config));
+    }
+
+    #[test]
+    fn forgotten_ids_can_be_sent_again() {
+        let guard = ReplayGuard::default();
+        let config = config(10, None);
+        assert!(guard.check("deploy", "a", &config));
+        guard.forget("deploy", "a", &config);
+        assert!(guard.check("deploy", "a", &config));
+    }
+
+    #[test]
+    fn ids_are_reloaded_from_the_persist_file() {
+        let path = persist_file("reload");
+        let config = config(2, Some(path.clone()));
+
+        let guard = ReplayGuard::default();
+        for id in ["a", "b", "c", "d"] {
+            assert!(guard.check("deploy", id, &config));
+        }
+        guard.forget("deploy", "d", &config);
+        assert!(guard.check("deploy", "e", &config));
+        guard.flush();
+
+        let reloaded = ReplayGuard::default();
+        let seen = ["c", "e", "d"].map(|id| !reloaded.check("deploy", id, &config));
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(seen, [true, true, false]);
+    }
+}
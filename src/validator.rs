@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use axum::{
     extract::{Path, Request, State},
     middleware::Next,
@@ -6,27 +8,59 @@ use axum::{
 use grhooks_core::render_secret;
 use grhooks_origin::{Origin, WebhookOrigin};
 
-use crate::{GlobalConfig, errors::HeaderValidationError};
+use crate::history::Delivery;
+use crate::jobs::JobStatus;
+use crate::redeliver::Redelivery;
+use crate::{AppState, GlobalConfig, errors::HeaderValidationError};
 
 pub async fn validate_headers(
-    request: Request,
+    Path(path): Path<String>,
+    State(config): State<GlobalConfig>,
+    mut request: Request,
     next: Next,
 ) -> Result<Response, HeaderValidationError> {
+    let config = config.read().await;
     let headers = request.headers();
-    let origin = Origin::try_from(headers)?;
-    origin.validate_headers(headers)?;
+
+    let webhook_config = config
+        .webhooks
+        .iter()
+        .find(|w| w.path == path)
+        .ok_or(HeaderValidationError::WebhookNotFound)?;
+
+    let origin = if webhook_config.origin.is_empty() {
+        let origin = Origin::try_from(headers)?;
+        origin.validate_headers(headers)?;
+        origin
+    } else {
+        // Explicit origins replace header sniffing: the first configured
+        // origin whose headers validate handles the delivery
+        webhook_config
+            .origin
+            .iter()
+            .copied()
+            .find(|origin| origin.validate_headers(headers).is_ok())
+            .ok_or_else(|| HeaderValidationError::OriginNotAllowed(webhook_config.origin.clone()))?
+    };
+    drop(config);
+
+    request.extensions_mut().insert(origin);
     Ok(next.run(request).await)
 }
 
 pub async fn validate_signature_middleware(
     Path(path): Path<String>,
-    State(config): State<GlobalConfig>,
+    State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<Response, HeaderValidationError> {
-    let config = config.read().await;
+    let config = state.config.read().await;
     let headers = request.headers().clone();
-    let origin = Origin::try_from(&headers)?;
+    let origin = request
+        .extensions()
+        .get::<Origin>()
+        .copied()
+        .ok_or(HeaderValidationError::MissingOrigin)?;
 
     let webhook_config = config
         .webhooks
@@ -34,20 +68,59 @@ pub async fn validate_signature_middleware(
         .find(|w| w.path == path)
         .ok_or(HeaderValidationError::WebhookNotFound)?;
 
-    let event_type = origin.extract_event_type(&headers)?;
+    let skip_signature = request
+        .extensions()
+        .get::<Redelivery>()
+        .is_some_and(|redelivery| redelivery.skip_signature);
+    if skip_signature {
+        return Ok(next.run(request).await);
+    }
+    // Configured secrets that failed to load never leave the webhook unsigned
+    if let Some(error) = &webhook_config.secret_error {
+        tracing::error!("Rejecting delivery on {path:?}: {error}");
+        return Err(HeaderValidationError::SecretUnavailable);
+    }
+    if webhook_config.secret.is_empty() {
+        return Ok(next.run(request).await);
+    }
 
-    if let Some(secret) = &webhook_config.secret {
-        let secret = render_secret(secret, &event_type);
-        let (parts, body) = request.into_parts();
-        let bytes = axum::body::to_bytes(body, usize::MAX)
-            .await
-            .map_err(HeaderValidationError::AxumError)?;
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(HeaderValidationError::AxumError)?;
 
-        origin.validate_signature(&headers, &secret, &bytes)?;
+    let payload = serde_json::from_slice(&bytes).unwrap_or_default();
+    let event_type = origin.extract_event_type(&headers, &payload)?;
 
-        let request = Request::from_parts(parts, axum::body::Body::from(bytes));
-        Ok(next.run(request).await)
-    } else {
-        Ok(next.run(request).await)
+    let tolerance = webhook_config
+        .tolerance
+        .map_or(grhooks_origin::DEFAULT_TOLERANCE, Duration::from_secs);
+    let mut result = Err(grhooks_origin::Error::InvalidSignature);
+    for (index, secret) in webhook_config.secret.iter().enumerate() {
+        let secret = render_secret(secret, &event_type);
+        result = origin.validate_signature_within(&headers, secret.expose(), &bytes, tolerance);
+        if result.is_ok() {
+            tracing::info!("Signature for {path:?} matched secret #{index}");
+            break;
+        }
     }
+    if let Err(e) = result {
+        tracing::warn!("Rejected delivery on {path:?}: {e:?}");
+        if let Some(history) = &state.history {
+            let id = origin
+                .extract_delivery_id(&headers)
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            let mut record =
+                Delivery::received(webhook_config, origin, &event_type, id, &headers, &bytes);
+            // Anyone can send unsigned bodies, only their size is kept
+            record.body.clear();
+            record.status = JobStatus::Rejected;
+            record.output = format!("{e:?}");
+            history.record(record).await;
+        }
+        return Err(e.into());
+    }
+
+    let request = Request::from_parts(parts, axum::body::Body::from(bytes));
+    Ok(next.run(request).await)
 }